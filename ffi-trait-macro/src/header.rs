@@ -0,0 +1,707 @@
+//! Generation of C headers for `ffi_trait`s.
+//!
+//! The header for a trait `Foo` is meant to be saved as `Foo.h`, since
+//! headers of subtraits and of methods taking other FFI trait objects
+//! `#include` it under that name.
+
+use syn::*;
+
+use std::fmt::Write;
+
+/// A method as seen from C
+pub struct CMethod<'a> {
+	pub name: &'a Ident,
+	pub inputs: Vec<&'a Type>,
+	pub output: &'a ReturnType,
+	/// Methods without an ABI use the Rust ABI, which C can't call
+	pub callable: bool,
+	/// Methods with the "system" ABI are `__stdcall` on 32-bit Windows
+	pub system: bool,
+	/// Methods with `on_panic = "catch"` return an `FFIUnwindResult`
+	pub catch: bool,
+	/// Methods taking `self` by value take ownership of the data
+	pub consumes: bool,
+	/// Methods added in a later version of the trait may be missing from the vtable
+	pub since: Option<u64>,
+	/// Optional methods may be null
+	pub optional: bool
+}
+
+/// The C types of the FFI trait object types, which hold a `dyn Trait`
+const DYN_WRAPPERS: &[&str] = &["FFIDynPtr", "FFIDynNonNull", "FFIDynRef", "FFIDynMut", "FFIDynBox"];
+/// Those which also hold an `FFIRcVtable`
+const RC_WRAPPERS: &[&str] = &["FFIDynArc", "FFIDynRc"];
+/// Weak references to those
+const WEAK_WRAPPERS: &[&str] = &["FFIDynWeak", "FFIDynRcWeak"];
+/// Those which are a single pointer to the vtable pointer followed by the data
+const THIN_WRAPPERS: &[&str] = &["FFIDynThinRef", "FFIDynThinBox"];
+/// The strings, slices and vectors, which are declared by `marshalled_types`
+const MARSHALLED: &[(&str, &str)] = &[
+	("FFIStr", "ffi_trait_str"),
+	("FFISlice", "ffi_trait_slice"),
+	("FFISliceMut", "ffi_trait_slice_mut"),
+	("FFIString", "ffi_trait_string"),
+	("FFIVec", "ffi_trait_vec")
+];
+
+fn primitive(ident: &Ident) -> Option<&'static str> {
+	Some(match &*ident.to_string() {
+		"u8" => "uint8_t",
+		"u16" => "uint16_t",
+		"u32" => "uint32_t",
+		"u64" => "uint64_t",
+		"i8" => "int8_t",
+		"i16" => "int16_t",
+		"i32" => "int32_t",
+		"i64" => "int64_t",
+		"usize" => "size_t",
+		"isize" => "ptrdiff_t",
+		"f32" => "float",
+		"f64" => "double",
+		"bool" => "bool",
+		"char" => "uint32_t",
+		"c_void" => "void",
+		"c_char" => "char",
+		"c_schar" => "signed char",
+		"c_uchar" => "unsigned char",
+		"c_short" => "short",
+		"c_ushort" => "unsigned short",
+		"c_int" => "int",
+		"c_uint" => "unsigned int",
+		"c_long" => "long",
+		"c_ulong" => "unsigned long",
+		"c_longlong" => "long long",
+		"c_ulonglong" => "unsigned long long",
+		"c_float" => "float",
+		"c_double" => "double",
+		_ => return None
+	})
+}
+
+/// The C name of an instantiation of a generic trait, e.g. `Sink_u32` for `Sink<u32>`
+pub fn mangle(name: &str) -> String {
+	let mut out = String::new();
+	for c in name.chars() {
+		if c.is_ascii_alphanumeric() {
+			out.push(c);
+		} else if !out.is_empty() && !out.ends_with('_') {
+			out.push('_');
+		}
+	}
+	out.trim_end_matches('_').to_string()
+}
+
+/// Finds the trait in a `dyn Trait` type
+fn dyn_trait(ty: &Type) -> Option<String> {
+	match ty {
+		Type::TraitObject(x) => x.bounds.iter().find_map(|x| match x {
+			TypeParamBound::Trait(x) => {
+				let seg = x.path.segments.last()?;
+				Some(match seg.arguments {
+					PathArguments::None => seg.ident.to_string(),
+					_ => mangle(&quote!(#seg).to_string())
+				})
+			},
+			_ => None
+		}),
+		Type::Paren(x) => dyn_trait(&x.elem),
+		Type::Group(x) => dyn_trait(&x.elem),
+		_ => None
+	}
+}
+
+fn generic_args(seg: &PathSegment) -> Vec<&Type> {
+	match &seg.arguments {
+		PathArguments::AngleBracketed(x) => x.args.iter().filter_map(|x| match x {
+			GenericArgument::Type(x) => Some(x),
+			_ => None
+		}).collect(),
+		_ => Vec::new()
+	}
+}
+
+/// Converts a Rust type to the equivalent C type, recording any headers it needs.
+/// Types which aren't known are assumed to be declared under the same name in C.
+pub fn c_type(ty: &Type, includes: &mut Vec<String>) -> String {
+	match ty {
+		Type::Tuple(x) if x.elems.is_empty() => "void".to_string(),
+		Type::Paren(x) => c_type(&x.elem, includes),
+		Type::Group(x) => c_type(&x.elem, includes),
+		Type::Ptr(x) => pointer(&x.elem, x.const_token.is_some(), includes),
+		Type::Reference(x) => pointer(&x.elem, x.mutability.is_none(), includes),
+		Type::BareFn(_) => "void *".to_string(),
+		Type::Path(x) => {
+			let seg = x.path.segments.last().unwrap();
+			let name = seg.ident.to_string();
+			let args = generic_args(seg);
+			if let Some(x) = primitive(&seg.ident) {
+				x.to_string()
+			} else if name == "NonNull" && args.len() == 1 {
+				pointer(args[0], false, includes)
+			} else if THIN_WRAPPERS.contains(&&*name) {
+				"void *".to_string()
+			} else if let Some((_, x)) = MARSHALLED.iter().find(|(x, _)| *x == name) {
+				x.to_string()
+			} else if name == "FFIOption" && args.len() == 1 {
+				format!("ffi_trait_option_{}", mangle(&c_type(args[0], includes)))
+			} else if name == "FFIResult" && args.len() == 2 {
+				format!("ffi_trait_result_{}_{}", mangle(&c_type(args[0], includes)), mangle(&c_type(args[1], includes)))
+			} else if name == "ComPtr" && args.len() == 1 {
+				// COM objects are thin pointers, and have one header for each interface
+				match dyn_trait(args[0]) {
+					Some(t) => {
+						let header = format!("{}.h", t);
+						if !includes.contains(&header) {
+							includes.push(header);
+						}
+						format!("{} *", t)
+					},
+					None => "void *".to_string()
+				}
+			} else if name == "Option" && args.len() == 1 {
+				// only nullable pointers are FFI-safe in an `Option`
+				c_type(args[0], includes)
+			} else if [DYN_WRAPPERS, RC_WRAPPERS, WEAK_WRAPPERS].iter().any(|x| x.contains(&&*name)) && !args.is_empty() {
+				match dyn_trait(args[0]) {
+					Some(t) => {
+						let header = format!("{}.h", t);
+						if !includes.contains(&header) {
+							includes.push(header);
+						}
+						let suffix = if RC_WRAPPERS.contains(&&*name) {
+							"rc"
+						} else if WEAK_WRAPPERS.contains(&&*name) {
+							"weak"
+						} else {
+							"ptr"
+						};
+						format!("{}_{}", t, suffix)
+					},
+					None => name
+				}
+			} else {
+				name
+			}
+		},
+		_ => "void *".to_string()
+	}
+}
+
+fn pointer(elem: &Type, constant: bool, includes: &mut Vec<String>) -> String {
+	let elem = c_type(elem, includes);
+	match (elem.ends_with('*'), constant) {
+		// the pointer being pointed to is what's const
+		(true, true) => format!("{}const *", elem),
+		(true, false) => format!("{}*", elem),
+		(false, true) => format!("const {} *", elem),
+		(false, false) => format!("{} *", elem)
+	}
+}
+
+fn output(x: &ReturnType, includes: &mut Vec<String>) -> String {
+	match x {
+		ReturnType::Default => "void".to_string(),
+		ReturnType::Type(_, ty) => c_type(ty, includes)
+	}
+}
+
+/// The C type returned by the vtable entry of a method
+fn raw_output(trait_name: &Ident, m: &CMethod, includes: &mut Vec<String>) -> String {
+	if m.catch {
+		format!("{}_{}_result", trait_name, m.name)
+	} else {
+		output(m.output, includes)
+	}
+}
+
+/// Joins a type and a name into a declaration, e.g. `const uint8_t *x`
+fn declare(ty: &str, name: &str) -> String {
+	if ty.ends_with('*') { format!("{}{}", ty, name) } else { format!("{} {}", ty, name) }
+}
+
+/// The part of a function pointer declaration before the `*`, e.g. `FFI_TRAIT_SYSTEM *`
+fn fn_pointer(m: &CMethod) -> &'static str {
+	if m.system { "FFI_TRAIT_SYSTEM *" } else { "*" }
+}
+
+/// Defines `FFI_TRAIT_SYSTEM`, the calling convention of the "system" ABI
+fn system_abi(body: &mut String) {
+	writeln!(body, "#ifndef FFI_TRAIT_SYSTEM").unwrap();
+	writeln!(body, "#if defined(_WIN32) && !defined(_WIN64)").unwrap();
+	writeln!(body, "#define FFI_TRAIT_SYSTEM __stdcall").unwrap();
+	writeln!(body, "#else").unwrap();
+	writeln!(body, "#define FFI_TRAIT_SYSTEM").unwrap();
+	writeln!(body, "#endif").unwrap();
+	writeln!(body, "#endif").unwrap();
+	writeln!(body).unwrap();
+}
+
+/// Declares the equivalents of `FFIStr`, `FFISlice`, `FFISliceMut`, `FFIString` and `FFIVec`,
+/// which are shared by the headers of every trait. Slices and vectors are untyped in C
+fn marshalled_types(body: &mut String) {
+	writeln!(body, "#ifndef FFI_TRAIT_SLICES").unwrap();
+	writeln!(body, "#define FFI_TRAIT_SLICES").unwrap();
+	writeln!(body, "typedef struct ffi_trait_str {{").unwrap();
+	writeln!(body, "\tconst char *ptr;").unwrap();
+	writeln!(body, "\tsize_t len;").unwrap();
+	writeln!(body, "}} ffi_trait_str;").unwrap();
+	writeln!(body, "typedef struct ffi_trait_slice {{").unwrap();
+	writeln!(body, "\tconst void *ptr;").unwrap();
+	writeln!(body, "\tsize_t len;").unwrap();
+	writeln!(body, "}} ffi_trait_slice;").unwrap();
+	writeln!(body, "typedef struct ffi_trait_slice_mut {{").unwrap();
+	writeln!(body, "\tvoid *ptr;").unwrap();
+	writeln!(body, "\tsize_t len;").unwrap();
+	writeln!(body, "}} ffi_trait_slice_mut;").unwrap();
+	// owned buffers are freed by the `drop` of whoever allocated them, which
+	// drops the first `len` elements too
+	writeln!(body, "typedef struct ffi_trait_string {{").unwrap();
+	writeln!(body, "\tchar *ptr;").unwrap();
+	writeln!(body, "\tsize_t len;").unwrap();
+	writeln!(body, "\tsize_t cap;").unwrap();
+	writeln!(body, "\tvoid (*drop)(char *ptr, size_t len, size_t cap);").unwrap();
+	writeln!(body, "}} ffi_trait_string;").unwrap();
+	writeln!(body, "typedef struct ffi_trait_vec {{").unwrap();
+	writeln!(body, "\tvoid *ptr;").unwrap();
+	writeln!(body, "\tsize_t len;").unwrap();
+	writeln!(body, "\tsize_t cap;").unwrap();
+	writeln!(body, "\tvoid (*drop)(void *ptr, size_t len, size_t cap);").unwrap();
+	writeln!(body, "}} ffi_trait_vec;").unwrap();
+	writeln!(body, "#endif").unwrap();
+	writeln!(body).unwrap();
+}
+
+/// Declares the equivalents of the `FFIOption`s and `FFIResult`s in a type, innermost
+/// first. Each is guarded, since the headers of other traits may use them as well
+fn option_types(ty: &Type, body: &mut String, includes: &mut Vec<String>) {
+	let seg = match ty {
+		Type::Paren(x) => return option_types(&x.elem, body, includes),
+		Type::Group(x) => return option_types(&x.elem, body, includes),
+		Type::Path(x) => x.path.segments.last().unwrap(),
+		_ => return
+	};
+	let args = generic_args(seg);
+	for x in &args {
+		option_types(x, body, includes);
+	}
+	let option = seg.ident == "FFIOption" && args.len() == 1;
+	let result = seg.ident == "FFIResult" && args.len() == 2;
+	if !option && !result {
+		return;
+	}
+	let name = c_type(ty, includes);
+	let guard = name.to_uppercase();
+	writeln!(body, "#ifndef {}", guard).unwrap();
+	writeln!(body, "#define {}", guard).unwrap();
+	writeln!(body, "typedef struct {} {{", name).unwrap();
+	if option {
+		writeln!(body, "\tbool is_some;").unwrap();
+		let value = c_type(args[0], includes);
+		if value != "void" {
+			writeln!(body, "\t{};", declare(&value, "value")).unwrap();
+		}
+	} else {
+		writeln!(body, "\tbool is_ok;").unwrap();
+		let values: Vec<_> = vec![("ok", args[0]), ("err", args[1])].into_iter()
+			.map(|(field, ty)| (field, c_type(ty, includes)))
+			.filter(|x| x.1 != "void")
+			.collect();
+		// C doesn't allow empty unions
+		if !values.is_empty() {
+			writeln!(body, "\tunion {{").unwrap();
+			for (field, ty) in values {
+				writeln!(body, "\t\t{};", declare(&ty, field)).unwrap();
+			}
+			writeln!(body, "\t}} value;").unwrap();
+		}
+	}
+	writeln!(body, "}} {};", name).unwrap();
+	writeln!(body, "#endif").unwrap();
+	writeln!(body).unwrap();
+}
+
+/// Declares the `FFIOption`s and `FFIResult`s of every method which C can call
+fn option_types_of(methods: &[&CMethod], body: &mut String, includes: &mut Vec<String>) {
+	for m in methods.iter().filter(|x| x.callable) {
+		for ty in &m.inputs {
+			option_types(ty, body, includes);
+		}
+		if let ReturnType::Type(_, ty) = m.output {
+			option_types(ty, body, includes);
+		}
+	}
+}
+
+/// Declares the equivalent of `FFIUnwindResult` for each method which catches panics,
+/// where `value` is only set if `panicked` is false
+fn unwind_results(name: &Ident, methods: &[&CMethod], body: &mut String, includes: &mut Vec<String>) {
+	for m in methods.iter().filter(|x| x.callable && x.catch) {
+		let result = format!("{}_{}_result", name, m.name);
+		writeln!(body, "typedef struct {} {{", result).unwrap();
+		writeln!(body, "\tbool panicked;").unwrap();
+		let ret = output(m.output, includes);
+		if ret != "void" {
+			writeln!(body, "\t{};", declare(&ret, "value")).unwrap();
+		}
+		writeln!(body, "}} {};", result).unwrap();
+		writeln!(body).unwrap();
+	}
+}
+
+/// Wraps the body of a header in its include guard and `#include`s
+fn finish(name: &Ident, body: &str, includes: &[String]) -> String {
+	let guard = format!("FFI_TRAIT_{}_H", name.to_string().to_uppercase());
+	let mut out = String::new();
+	writeln!(out, "/* generated by `#[ffi_trait]` for `{}`, do not edit */", name).unwrap();
+	writeln!(out, "#ifndef {}", guard).unwrap();
+	writeln!(out, "#define {}", guard).unwrap();
+	writeln!(out).unwrap();
+	writeln!(out, "#include <stdbool.h>").unwrap();
+	writeln!(out, "#include <stddef.h>").unwrap();
+	writeln!(out, "#include <stdint.h>").unwrap();
+	for x in includes.iter().filter(|x| **x != format!("{}.h", name)) {
+		writeln!(out, "#include \"{}\"", x).unwrap();
+	}
+	writeln!(out).unwrap();
+	out.push_str(body);
+	writeln!(out).unwrap();
+	writeln!(out, "#endif").unwrap();
+	out
+}
+
+pub fn generate(name: &Ident, supers: &[&Ident], consts: &[(&Ident, &Type)], methods: &[&CMethod], type_id: bool, query_interface: bool, interface_id: u128) -> String {
+	let mut includes = Vec::new();
+	let mut body = String::new();
+
+	for s in supers {
+		includes.push(format!("{}.h", s));
+	}
+
+	let vtable = format!("{}_vtable", name);
+	let ptr = format!("{}_ptr", name);
+	let rc = format!("{}_rc", name);
+	let weak = format!("{}_weak", name);
+
+	// shared by the headers of every trait
+	writeln!(body, "#ifndef FFI_TRAIT_RC_VTABLE").unwrap();
+	writeln!(body, "#define FFI_TRAIT_RC_VTABLE").unwrap();
+	writeln!(body, "typedef struct ffi_trait_rc_vtable {{").unwrap();
+	writeln!(body, "\tvoid (*retain)(const void *);").unwrap();
+	writeln!(body, "\tvoid (*release)(const void *);").unwrap();
+	writeln!(body, "\tvoid (*downgrade)(const void *);").unwrap();
+	writeln!(body, "\tbool (*upgrade)(const void *);").unwrap();
+	writeln!(body, "\tvoid (*retain_weak)(const void *);").unwrap();
+	writeln!(body, "\tvoid (*release_weak)(const void *);").unwrap();
+	writeln!(body, "}} ffi_trait_rc_vtable;").unwrap();
+	writeln!(body, "#endif").unwrap();
+	writeln!(body).unwrap();
+	// the equivalent of `FFITypeId`
+	writeln!(body, "#ifndef FFI_TRAIT_TYPE_ID").unwrap();
+	writeln!(body, "#define FFI_TRAIT_TYPE_ID").unwrap();
+	writeln!(body, "typedef struct ffi_trait_type_id {{").unwrap();
+	writeln!(body, "\tuint64_t high;").unwrap();
+	writeln!(body, "\tuint64_t low;").unwrap();
+	writeln!(body, "}} ffi_trait_type_id;").unwrap();
+	writeln!(body, "#endif").unwrap();
+	writeln!(body).unwrap();
+
+	// the equivalent of `FFIDynPtr<dyn FFIUnknown>`, which `query_interface` returns
+	writeln!(body, "#ifndef FFI_TRAIT_UNKNOWN_PTR").unwrap();
+	writeln!(body, "#define FFI_TRAIT_UNKNOWN_PTR").unwrap();
+	writeln!(body, "typedef struct ffi_trait_unknown_ptr {{").unwrap();
+	writeln!(body, "\tvoid *data;").unwrap();
+	writeln!(body, "\tconst void *vtable;").unwrap();
+	writeln!(body, "}} ffi_trait_unknown_ptr;").unwrap();
+	writeln!(body, "#endif").unwrap();
+	writeln!(body).unwrap();
+	marshalled_types(&mut body);
+
+	if methods.iter().any(|x| x.callable && x.system) {
+		system_abi(&mut body);
+	}
+
+	writeln!(body, "typedef struct {} {};", vtable, vtable).unwrap();
+	writeln!(body, "typedef struct {} {};", ptr, ptr).unwrap();
+	writeln!(body, "typedef struct {} {};", rc, rc).unwrap();
+	writeln!(body, "typedef struct {} {};", weak, weak).unwrap();
+	writeln!(body).unwrap();
+
+	option_types_of(methods, &mut body, &mut includes);
+	unwind_results(name, methods, &mut body, &mut includes);
+	writeln!(body, "struct {} {{", vtable).unwrap();
+	writeln!(body, "\tsize_t size;").unwrap();
+	writeln!(body, "\tsize_t align;").unwrap();
+	writeln!(body, "\tvoid (*drop_in_place)(void *);").unwrap();
+	writeln!(body, "\tvoid (*dealloc)(void *);").unwrap();
+	writeln!(body, "\tsize_t vtable_size;").unwrap();
+	writeln!(body, "\tconst void *rust; /* set by Rust, NULL from C */").unwrap();
+	if type_id {
+		writeln!(body, "\tffi_trait_type_id (*type_id)(void); /* may be missing, returns zeros for types without one */").unwrap();
+	}
+	if query_interface {
+		writeln!(body, "\tffi_trait_unknown_ptr (*query_interface)(void *self, ffi_trait_type_id id);").unwrap();
+	}
+	for s in supers {
+		writeln!(body, "\t{}_vtable super_{}; /* filled in too, with the same size, align, drop_in_place and dealloc */", s, s).unwrap();
+	}
+	// the associated constants of the implementor
+	for (name, ty) in consts {
+		writeln!(body, "\t{};", declare(&c_type(ty, &mut includes), &name.to_string())).unwrap();
+	}
+	for m in methods {
+		if !m.callable {
+			writeln!(body, "\tvoid *{}; /* Rust ABI, can't be called from C */", m.name).unwrap();
+			continue;
+		}
+		let mut params = vec!["void *self".to_string()];
+		for (i, ty) in m.inputs.iter().enumerate() {
+			params.push(declare(&c_type(ty, &mut includes), &format!("arg{}", i)));
+		}
+		let ret = raw_output(name, m, &mut includes);
+		let comment = if m.consumes {
+			" /* takes ownership of self */".to_string()
+		} else if let Some(since) = m.since {
+			format!(" /* since {}, may be missing */", since)
+		} else if m.optional {
+			" /* optional, may be null */".to_string()
+		} else {
+			String::new()
+		};
+		writeln!(body, "\t{};{}", declare(&ret, &format!("({}{})({})", fn_pointer(m), m.name, params.join(", "))), comment).unwrap();
+	}
+	writeln!(body, "}};").unwrap();
+	writeln!(body).unwrap();
+
+	writeln!(body, "struct {} {{", ptr).unwrap();
+	writeln!(body, "\tvoid *data;").unwrap();
+	writeln!(body, "\tconst {} *vtable;", vtable).unwrap();
+	writeln!(body, "}};").unwrap();
+	writeln!(body).unwrap();
+
+	// the equivalent of `FFIDynArc` and `FFIDynRc`
+	writeln!(body, "struct {} {{", rc).unwrap();
+	writeln!(body, "\tvoid *data;").unwrap();
+	writeln!(body, "\tconst {} *vtable;", vtable).unwrap();
+	writeln!(body, "\tconst ffi_trait_rc_vtable *rc;").unwrap();
+	writeln!(body, "}};").unwrap();
+	writeln!(body).unwrap();
+
+	// the equivalent of `FFIDynWeak` and `FFIDynRcWeak`
+	writeln!(body, "struct {} {{", weak).unwrap();
+	writeln!(body, "\tvoid *data;").unwrap();
+	writeln!(body, "\tconst {} *vtable;", vtable).unwrap();
+	writeln!(body, "\tconst ffi_trait_rc_vtable *rc;").unwrap();
+	writeln!(body, "}};").unwrap();
+
+	for m in methods.iter().filter(|x| x.callable) {
+		let mut params = vec![format!("{} self", ptr)];
+		let mut args = vec!["self.data".to_string()];
+		for (i, ty) in m.inputs.iter().enumerate() {
+			params.push(declare(&c_type(ty, &mut includes), &format!("arg{}", i)));
+			args.push(format!("arg{}", i));
+		}
+		let ret = raw_output(name, m, &mut includes);
+		let call = format!("self.vtable->{}({})", m.name, args.join(", "));
+		writeln!(body).unwrap();
+		writeln!(body, "static inline {}({}) {{", declare(&ret, &format!("{}_{}", name, m.name)), params.join(", ")).unwrap();
+		if ret == "void" {
+			writeln!(body, "\t{};", call).unwrap();
+		} else {
+			writeln!(body, "\treturn {};", call).unwrap();
+		}
+		writeln!(body, "}}").unwrap();
+
+		// vtables made for an older version of the trait end before the method
+		if m.since.is_some() || m.optional {
+			writeln!(body).unwrap();
+			writeln!(body, "static inline bool {}_has_{}({} self) {{", name, m.name, ptr).unwrap();
+			writeln!(body, "\treturn self.vtable->vtable_size >= offsetof({}, {}) + sizeof(self.vtable->{}) && self.vtable->{};", vtable, m.name, m.name, m.name).unwrap();
+			writeln!(body, "}}").unwrap();
+		}
+	}
+
+	// the equivalent of `Interface::ID`, which `query_interface` is called with
+	writeln!(body).unwrap();
+	writeln!(body, "static inline ffi_trait_type_id {}_interface_id(void) {{", name).unwrap();
+	writeln!(body, "\tffi_trait_type_id x = {{ UINT64_C(0x{:016x}), UINT64_C(0x{:016x}) }};", (interface_id >> 64) as u64, interface_id as u64).unwrap();
+	writeln!(body, "\treturn x;").unwrap();
+	writeln!(body, "}}").unwrap();
+
+	// the data is null if the object doesn't provide the interface
+	if query_interface {
+		writeln!(body).unwrap();
+		writeln!(body, "static inline ffi_trait_unknown_ptr {}_query_interface({} self, ffi_trait_type_id id) {{", name, ptr).unwrap();
+		writeln!(body, "\tif (self.vtable->query_interface) return self.vtable->query_interface(self.data, id);").unwrap();
+		writeln!(body, "\tffi_trait_unknown_ptr x = {{ NULL, NULL }};").unwrap();
+		writeln!(body, "\treturn x;").unwrap();
+		writeln!(body, "}}").unwrap();
+	}
+
+	for s in supers {
+		writeln!(body).unwrap();
+		writeln!(body, "static inline {}_ptr {}_as_{}({} self) {{", s, name, s, ptr).unwrap();
+		writeln!(body, "\t{}_ptr x = {{ self.data, &self.vtable->super_{} }};", s, s).unwrap();
+		writeln!(body, "\treturn x;").unwrap();
+		writeln!(body, "}}").unwrap();
+	}
+
+	writeln!(body).unwrap();
+	writeln!(body, "static inline {} {}_get({} self) {{", ptr, rc, rc).unwrap();
+	writeln!(body, "\t{} x = {{ self.data, self.vtable }};", ptr).unwrap();
+	writeln!(body, "\treturn x;").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+	writeln!(body, "static inline {} {}_clone({} self) {{", rc, rc, rc).unwrap();
+	writeln!(body, "\tself.rc->retain(self.data);").unwrap();
+	writeln!(body, "\treturn self;").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+	writeln!(body, "static inline void {}_drop({} self) {{", rc, rc).unwrap();
+	writeln!(body, "\tself.rc->release(self.data);").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+	writeln!(body, "static inline {} {}_downgrade({} self) {{", weak, rc, rc).unwrap();
+	writeln!(body, "\tself.rc->downgrade(self.data);").unwrap();
+	writeln!(body, "\t{} x = {{ self.data, self.vtable, self.rc }};", weak).unwrap();
+	writeln!(body, "\treturn x;").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+	// writes a new strong reference to `out` if the data is still alive
+	writeln!(body, "static inline bool {}_upgrade({} self, {} *out) {{", weak, weak, rc).unwrap();
+	writeln!(body, "\tif (!self.rc->upgrade(self.data)) return false;").unwrap();
+	writeln!(body, "\tout->data = self.data;").unwrap();
+	writeln!(body, "\tout->vtable = self.vtable;").unwrap();
+	writeln!(body, "\tout->rc = self.rc;").unwrap();
+	writeln!(body, "\treturn true;").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+	writeln!(body, "static inline {} {}_clone({} self) {{", weak, weak, weak).unwrap();
+	writeln!(body, "\tself.rc->retain_weak(self.data);").unwrap();
+	writeln!(body, "\treturn self;").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+	writeln!(body, "static inline void {}_drop({} self) {{", weak, weak).unwrap();
+	writeln!(body, "\tself.rc->release_weak(self.data);").unwrap();
+	writeln!(body, "}}").unwrap();
+
+	// the equivalent of `FFIDynThinRef::to_ref`, where the data follows the vtable pointer
+	writeln!(body).unwrap();
+	writeln!(body, "static inline {} {}_thin_get(void *thin) {{", ptr, name).unwrap();
+	writeln!(body, "\tconst {} *vtable = *(const {} **)thin;", vtable, vtable).unwrap();
+	writeln!(body, "\tsize_t offset = (sizeof(void *) + vtable->align - 1) & ~(vtable->align - 1);").unwrap();
+	writeln!(body, "\t{} x = {{ (char *)thin + offset, vtable }};", ptr).unwrap();
+	writeln!(body, "\treturn x;").unwrap();
+	writeln!(body, "}}").unwrap();
+
+	// equivalent of dropping an `FFIDynThinBox`, whose allocation is freed by the
+	// function before the vtable pointer
+	writeln!(body).unwrap();
+	writeln!(body, "static inline void {}_thin_drop(void *thin) {{", name).unwrap();
+	writeln!(body, "	{} x = {}_thin_get(thin);", ptr, name).unwrap();
+	writeln!(body, "	if (x.vtable->drop_in_place) x.vtable->drop_in_place(x.data);").unwrap();
+	writeln!(body, "	(*((void (**)(void *))thin - 1))(thin);").unwrap();
+	writeln!(body, "}}").unwrap();
+
+	// equivalent of dropping an `FFIDynBox`
+	writeln!(body).unwrap();
+	writeln!(body, "static inline void {}_drop({} self) {{", name, ptr).unwrap();
+	writeln!(body, "\tif (self.vtable->drop_in_place) self.vtable->drop_in_place(self.data);").unwrap();
+	writeln!(body, "\tif (self.vtable->dealloc) self.vtable->dealloc(self.data);").unwrap();
+	writeln!(body, "}}").unwrap();
+
+	finish(name, &body, &includes)
+}
+
+/// The header of a `layout = "com"` trait, where the object is a pointer to a
+/// struct beginning with the vtable pointer, like a C++ class or a COM object in C
+pub fn generate_com(name: &Ident, methods: &[&CMethod], iid: [u8; 16]) -> String {
+	let mut includes = Vec::new();
+	let mut body = String::new();
+	let vtable = format!("{}_vtable", name);
+
+	// the equivalent of `Guid`
+	writeln!(body, "#ifndef FFI_TRAIT_GUID").unwrap();
+	writeln!(body, "#define FFI_TRAIT_GUID").unwrap();
+	writeln!(body, "typedef struct ffi_trait_guid {{").unwrap();
+	writeln!(body, "\tuint32_t data1;").unwrap();
+	writeln!(body, "\tuint16_t data2;").unwrap();
+	writeln!(body, "\tuint16_t data3;").unwrap();
+	writeln!(body, "\tuint8_t data4[8];").unwrap();
+	writeln!(body, "}} ffi_trait_guid;").unwrap();
+	writeln!(body, "#endif").unwrap();
+	writeln!(body).unwrap();
+	marshalled_types(&mut body);
+	system_abi(&mut body);
+
+	writeln!(body, "typedef struct {} {};", name, name).unwrap();
+	writeln!(body, "typedef struct {} {};", vtable, vtable).unwrap();
+	writeln!(body).unwrap();
+	option_types_of(methods, &mut body, &mut includes);
+	unwind_results(name, methods, &mut body, &mut includes);
+
+	writeln!(body, "struct {} {{", vtable).unwrap();
+	writeln!(body, "\tint32_t (FFI_TRAIT_SYSTEM *QueryInterface)({} *self, const ffi_trait_guid *iid, void **out);", name).unwrap();
+	writeln!(body, "\tuint32_t (FFI_TRAIT_SYSTEM *AddRef)({} *self);", name).unwrap();
+	writeln!(body, "\tuint32_t (FFI_TRAIT_SYSTEM *Release)({} *self);", name).unwrap();
+	for m in methods {
+		if !m.callable {
+			writeln!(body, "\tvoid *{}; /* Rust ABI, can't be called from C */", m.name).unwrap();
+			continue;
+		}
+		let mut params = vec![format!("{} *self", name)];
+		for (i, ty) in m.inputs.iter().enumerate() {
+			params.push(declare(&c_type(ty, &mut includes), &format!("arg{}", i)));
+		}
+		let ret = raw_output(name, m, &mut includes);
+		writeln!(body, "\t{};", declare(&ret, &format!("({}{})({})", fn_pointer(m), m.name, params.join(", ")))).unwrap();
+	}
+	writeln!(body, "}};").unwrap();
+	writeln!(body).unwrap();
+
+	writeln!(body, "struct {} {{", name).unwrap();
+	writeln!(body, "\tconst {} *vtable;", vtable).unwrap();
+	writeln!(body, "}};").unwrap();
+	writeln!(body).unwrap();
+
+	// the equivalent of `ComInterface::IID`
+	writeln!(body, "static inline ffi_trait_guid {}_iid(void) {{", name).unwrap();
+	let data4 = iid[8..].iter().map(|x| format!("0x{:02x}", x)).collect::<Vec<_>>();
+	writeln!(body, "\tffi_trait_guid x = {{ 0x{:02x}{:02x}{:02x}{:02x}, 0x{:02x}{:02x}, 0x{:02x}{:02x}, {{ {} }} }};", iid[0], iid[1], iid[2], iid[3], iid[4], iid[5], iid[6], iid[7], data4.join(", ")).unwrap();
+	writeln!(body, "\treturn x;").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+
+	// `out` is set to a new reference to the object as the other interface, or null
+	writeln!(body, "static inline int32_t {}_QueryInterface({} *self, const ffi_trait_guid *iid, void **out) {{", name, name).unwrap();
+	writeln!(body, "\treturn self->vtable->QueryInterface(self, iid, out);").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+	writeln!(body, "static inline uint32_t {}_AddRef({} *self) {{", name, name).unwrap();
+	writeln!(body, "\treturn self->vtable->AddRef(self);").unwrap();
+	writeln!(body, "}}").unwrap();
+	writeln!(body).unwrap();
+	writeln!(body, "static inline uint32_t {}_Release({} *self) {{", name, name).unwrap();
+	writeln!(body, "\treturn self->vtable->Release(self);").unwrap();
+	writeln!(body, "}}").unwrap();
+
+	for m in methods.iter().filter(|x| x.callable) {
+		let mut params = vec![format!("{} *self", name)];
+		let mut args = vec!["self".to_string()];
+		for (i, ty) in m.inputs.iter().enumerate() {
+			params.push(declare(&c_type(ty, &mut includes), &format!("arg{}", i)));
+			args.push(format!("arg{}", i));
+		}
+		let ret = raw_output(name, m, &mut includes);
+		let call = format!("self->vtable->{}({})", m.name, args.join(", "));
+		writeln!(body).unwrap();
+		writeln!(body, "static inline {}({}) {{", declare(&ret, &format!("{}_{}", name, m.name)), params.join(", ")).unwrap();
+		if ret == "void" {
+			writeln!(body, "\t{};", call).unwrap();
+		} else {
+			writeln!(body, "\treturn {};", call).unwrap();
+		}
+		writeln!(body, "}}").unwrap();
+	}
+
+	finish(name, &body, &includes)
+}
@@ -1,13 +1,427 @@
-#![feature(proc_macro_def_site)]
-
 #[macro_use] extern crate quote;
 
+mod header;
+
 use syn::*; // note: std result gets shadowed
+use syn::spanned::Spanned;
 
 use proc_macro::Span;
 use proc_macro::TokenStream;
 
 use std::cell::RefCell;
+use std::collections::HashMap;
+
+struct MethodParts<'a> {
+	fnty: proc_macro2::TokenStream,
+	method: proc_macro2::TokenStream,
+	rawmethod: proc_macro2::TokenStream,
+	name: &'a Ident,
+	rawname: Ident,
+	methodimpl: proc_macro2::TokenStream,
+	mutable: bool,
+	consumes: bool,
+	since: Option<u64>,
+	/// Whether the vtable slot may be null, for methods with `since` or `optional`
+	nullable: bool,
+	/// The types which must be `FfiSafe`
+	checked: Vec<Type>,
+	cmethod: header::CMethod<'a>
+}
+
+/// A method's signature with the pinned associated types filled in, and how it
+/// goes through the vtable
+struct VtableSig {
+	sig: Signature,
+	/// The output of the vtable entry
+	output: ReturnType,
+	/// The bound of the boxed associated type it returns
+	boxed_assoc: Option<Path>,
+	/// How the output is translated to the vtable's
+	marshal: Option<Marshal>,
+	/// The types of the arguments in the vtable, and how they're translated to them
+	inputs: Vec<(Type, Option<Marshal>)>
+}
+
+/// What a raw method shim does when the Rust method panics
+#[derive(Copy, Clone)]
+enum OnPanic {
+	Abort,
+	Catch
+}
+
+// "unwind" leaves it to the ABI, which aborts for "C" and unwinds into the caller for "C-unwind"
+fn on_panic_from_lit(x: Lit) -> Result<Option<OnPanic>> {
+	match x {
+		Lit::Str(x) if x.value() == "unwind" => Ok(None),
+		Lit::Str(x) if x.value() == "abort" => Ok(Some(OnPanic::Abort)),
+		Lit::Str(x) if x.value() == "catch" => Ok(Some(OnPanic::Catch)),
+		x => Err(Error::new_spanned(x, "`on_panic` must be \"unwind\", \"abort\" or \"catch\""))
+	}
+}
+
+/// Whether a type is `Self`
+fn is_self(ty: &Type) -> bool {
+	match ty {
+		Type::Path(x) => x.qself.is_none() && x.path.is_ident("Self"),
+		_ => false
+	}
+}
+
+/// Whether a type is `Self::name`
+fn is_self_assoc(ty: &Type, name: &Ident) -> bool {
+	match ty {
+		Type::Path(x) => x.qself.is_none() && x.path.segments.len() == 2 && x.path.segments[0].ident == "Self" && x.path.segments[1].ident == *name,
+		_ => false
+	}
+}
+
+/// Whether an identifier, e.g. `Self`, appears anywhere in the tokens
+fn mentions_ident(tokens: proc_macro2::TokenStream, name: &str) -> bool {
+	tokens.into_iter().any(|x| match x {
+		proc_macro2::TokenTree::Ident(x) => x == name,
+		proc_macro2::TokenTree::Group(x) => mentions_ident(x.stream(), name),
+		_ => false
+	})
+}
+
+/// Whether `Self::name` appears anywhere in the tokens
+fn mentions_self_assoc(tokens: proc_macro2::TokenStream, name: &Ident) -> bool {
+	use proc_macro2::TokenTree;
+
+	let tokens = tokens.into_iter().collect::<Vec<_>>();
+	tokens.windows(4).any(|x| matches!(x,
+		[TokenTree::Ident(s), TokenTree::Punct(a), TokenTree::Punct(b), TokenTree::Ident(n)]
+		if s == "Self" && a.as_char() == ':' && b.as_char() == ':' && n == name
+	)) || tokens.iter().any(|x| matches!(x, TokenTree::Group(x) if mentions_self_assoc(x.stream(), name)))
+}
+
+/// Replaces `Self::Name` with the type an associated type is pinned to
+fn substitute_assoc(tokens: proc_macro2::TokenStream, pins: &[(&Ident, &Type)]) -> proc_macro2::TokenStream {
+	use proc_macro2::TokenTree;
+
+	let tokens = tokens.into_iter().collect::<Vec<_>>();
+	let mut out = proc_macro2::TokenStream::new();
+	let mut i = 0;
+	while i < tokens.len() {
+		if let [TokenTree::Ident(s), TokenTree::Punct(a), TokenTree::Punct(b), TokenTree::Ident(name), ..] = &tokens[i..] {
+			if s == "Self" && a.as_char() == ':' && b.as_char() == ':' {
+				if let Some((_, ty)) = pins.iter().find(|(x, _)| *x == name) {
+					out.extend(quote!(#ty));
+					i += 4;
+					continue;
+				}
+			}
+		}
+		match &tokens[i] {
+			TokenTree::Group(x) => {
+				let mut group = proc_macro2::Group::new(x.delimiter(), substitute_assoc(x.stream(), pins));
+				group.set_span(x.span());
+				out.extend(quote!(#group));
+			},
+			x => out.extend(quote!(#x))
+		}
+		i += 1;
+	}
+	out
+}
+
+/// How an associated type of a trait is passed through the vtable
+enum Assoc {
+	/// `#[ffi_trait(assoc(Name = Type))]`, every implementor uses this type
+	Pinned(Box<Type>),
+	/// `type Name: Bound`, carried as an `FFIDynBox<dyn Bound>`
+	Boxed(Path)
+}
+
+/// Whether a type is `Box<Self>`
+fn is_box_self(ty: &Type) -> bool {
+	match ty {
+		Type::Path(x) if x.qself.is_none() => {
+			let last = x.path.segments.last().unwrap();
+			last.ident == "Box" && match &last.arguments {
+				PathArguments::AngleBracketed(x) => x.args.len() == 1 && matches!(&x.args[0], GenericArgument::Type(x) if is_self(x)),
+				_ => false
+			}
+		},
+		_ => false
+	}
+}
+
+/// A type in a method signature which goes through the vtable as one of the FFI types
+#[derive(Clone)]
+enum Marshal {
+	/// `&dyn Trait`, passed as an `FFIDynRef`
+	DynRef(Path),
+	/// `&mut dyn Trait`, passed as an `FFIDynMut`
+	DynMut(Path),
+	/// `Box<dyn Trait>`, passed as an `FFIDynBox`
+	DynBox(Path),
+	/// `&str`, passed as an `FFIStr`
+	Str(Option<Lifetime>),
+	/// `&[T]`, passed as an `FFISlice`
+	Slice(Option<Lifetime>, Box<Type>),
+	/// `&mut [T]`, passed as an `FFISliceMut`
+	SliceMut(Option<Lifetime>, Box<Type>),
+	/// `String`, passed as an `FFIString`
+	String,
+	/// `Vec<T>`, passed as an `FFIVec`
+	Vec(Box<Type>),
+	/// `Option<T>`, passed as an `FFIOption` of `T` or of what `T` is passed as
+	Option(Box<Inner>),
+	/// `Result<T, E>`, passed as an `FFIResult`
+	Result(Box<Inner>, Box<Inner>)
+}
+
+/// A type inside an `Option` or `Result`, which may be translated itself
+type Inner = (Type, Option<Marshal>);
+
+/// The type in the vtable of a type inside an `Option` or `Result`
+fn inner_raw((ty, m): &Inner, ret: bool) -> Type {
+	match m {
+		Some(m) => m.raw(ret),
+		None => ty.clone()
+	}
+}
+
+/// Turns what's inside an `Option` or `Result` with `f`, which is given the variable
+/// holding it, in a closure for `map`
+fn inner_map((_, m): &Inner, f: impl FnOnce(&Marshal, &Ident) -> proc_macro2::TokenStream) -> Option<proc_macro2::TokenStream> {
+	let x = Ident::new("x", proc_macro2::Span::call_site());
+	m.as_ref().map(|m| {
+		let body = f(m, &x);
+		quote!(|#x| #body)
+	})
+}
+
+impl Marshal {
+	/// The type in the vtable. A function pointer can't tie the lifetime of its output
+	/// to its arguments, so borrows are returned as `'static` there
+	fn raw(&self, ret: bool) -> Type {
+		let lt = |x: &Option<Lifetime>| match x {
+			_ if ret => quote!('static),
+			Some(x) => quote!(#x),
+			None => quote!('_)
+		};
+		match self {
+			Marshal::DynRef(bound) => parse_quote!(ffi_trait::FFIDynRef<'_, dyn #bound + '_>),
+			Marshal::DynMut(bound) => parse_quote!(ffi_trait::FFIDynMut<'_, dyn #bound + '_>),
+			Marshal::DynBox(bound) => parse_quote!(ffi_trait::FFIDynBox<dyn #bound>),
+			Marshal::Str(x) => {
+				let lt = lt(x);
+				parse_quote!(ffi_trait::FFIStr<#lt>)
+			},
+			Marshal::Slice(x, ty) => {
+				let lt = lt(x);
+				parse_quote!(ffi_trait::FFISlice<#lt, #ty>)
+			},
+			Marshal::SliceMut(x, ty) => {
+				let lt = lt(x);
+				parse_quote!(ffi_trait::FFISliceMut<#lt, #ty>)
+			},
+			Marshal::String => parse_quote!(ffi_trait::FFIString),
+			Marshal::Vec(ty) => parse_quote!(ffi_trait::FFIVec<#ty>),
+			Marshal::Option(x) => {
+				let x = inner_raw(x, ret);
+				parse_quote!(ffi_trait::FFIOption<#x>)
+			},
+			Marshal::Result(ok, err) => {
+				let ok = inner_raw(ok, ret);
+				let err = inner_raw(err, ret);
+				parse_quote!(ffi_trait::FFIResult<#ok, #err>)
+			}
+		}
+	}
+
+	/// Turns the Rust value into the FFI type
+	fn wrap(&self, x: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+		match self {
+			Marshal::DynRef(_) => quote!(ffi_trait::FFIDynRef::from_std(#x)),
+			Marshal::DynMut(_) => quote!(ffi_trait::FFIDynMut::from_std(#x)),
+			Marshal::DynBox(bound) => quote!(ffi_trait::FFIDynBox::<dyn #bound>::from_std(#x)),
+			Marshal::Str(_) => quote!(ffi_trait::FFIStr::from_std(#x)),
+			Marshal::Slice(..) => quote!(ffi_trait::FFISlice::from_std(#x)),
+			Marshal::SliceMut(..) => quote!(ffi_trait::FFISliceMut::from_std(#x)),
+			Marshal::String => quote!(ffi_trait::FFIString::from_std(#x)),
+			Marshal::Vec(_) => quote!(ffi_trait::FFIVec::from_std(#x)),
+			Marshal::Option(_) | Marshal::Result(..) => self.map(x, |m, x| m.wrap(quote!(#x)), true)
+		}
+	}
+
+	/// Turns a returned Rust value into the FFI type, which has a `'static` lifetime
+	/// if it's a borrow
+	fn wrap_output(&self, x: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+		match self {
+			Marshal::Str(_) => quote!({
+				let x: &str = #x;
+				ffi_trait::FFIStr::from_raw_parts(x.as_ptr(), x.len())
+			}),
+			Marshal::Slice(..) => quote!({
+				let x: &[_] = #x;
+				ffi_trait::FFISlice::from_raw_parts(x.as_ptr(), x.len())
+			}),
+			Marshal::SliceMut(..) => quote!({
+				let x: &mut [_] = #x;
+				ffi_trait::FFISliceMut::from_raw_parts(x.as_mut_ptr(), x.len())
+			}),
+			Marshal::Option(_) | Marshal::Result(..) => self.map(x, |m, x| m.wrap_output(quote!(#x)), true),
+			_ => self.wrap(x)
+		}
+	}
+
+	/// Converts an `Option` or `Result` to or from the FFI type, and what's inside it with `f`
+	fn map(&self, x: proc_macro2::TokenStream, f: impl Fn(&Marshal, &Ident) -> proc_macro2::TokenStream, to_ffi: bool) -> proc_macro2::TokenStream {
+		match self {
+			Marshal::Option(inner) => {
+				let x = if to_ffi { x } else { quote!(#x.into_option()) };
+				let x = match inner_map(inner, &f) {
+					Some(f) => quote!(::core::option::Option::map(#x, #f)),
+					None => x
+				};
+				if to_ffi { quote!(ffi_trait::FFIOption::from_std(#x)) } else { x }
+			},
+			Marshal::Result(ok, err) => {
+				let x = if to_ffi { x } else { quote!(#x.into_result()) };
+				let x = match inner_map(ok, &f) {
+					Some(f) => quote!(::core::result::Result::map(#x, #f)),
+					None => x
+				};
+				let x = match inner_map(err, &f) {
+					Some(f) => quote!(::core::result::Result::map_err(#x, #f)),
+					None => x
+				};
+				if to_ffi { quote!(ffi_trait::FFIResult::from_std(#x)) } else { x }
+			},
+			_ => unreachable!()
+		}
+	}
+
+	/// Turns the FFI type in the variable `x` back into the Rust value
+	fn unwrap(&self, x: &Ident) -> proc_macro2::TokenStream {
+		match self {
+			// the trait picks a wrapper which implements it
+			Marshal::DynRef(_) => quote!(ffi_trait::__ffi_trait__AsDyn::__ffi_trait__as_dyn(&#x)),
+			Marshal::DynMut(_) => quote!(&mut { #x }),
+			Marshal::DynBox(bound) => quote!(ffi_trait::__Box::new(#x) as ffi_trait::__Box<dyn #bound>),
+			Marshal::Str(_) => quote!(#x.as_str()),
+			Marshal::Slice(..) => quote!(#x.as_slice()),
+			Marshal::SliceMut(..) => quote!(#x.into_slice()),
+			Marshal::String => quote!(#x.into_string()),
+			Marshal::Vec(_) => quote!(#x.into_vec()),
+			Marshal::Option(_) | Marshal::Result(..) => self.map(quote!(#x), |m, x| m.unwrap(x), false)
+		}
+	}
+}
+
+/// Whether a supertrait is `Send` or `Sync`, which only the wrappers need to implement
+fn is_auto_trait(x: &TypeParamBound) -> bool {
+	matches!(x, TypeParamBound::Trait(x) if x.path.segments.last().is_some_and(|x| x.ident == "Send" || x.ident == "Sync"))
+}
+
+/// The trait of a `dyn Trait` without other bounds
+fn dyn_bound(ty: &Type) -> Option<&Path> {
+	match ty {
+		Type::TraitObject(x) if x.dyn_token.is_some() && x.bounds.len() == 1 => match &x.bounds[0] {
+			TypeParamBound::Trait(x) if matches!(x.modifier, TraitBoundModifier::None) && x.lifetimes.is_none() => Some(&x.path),
+			_ => None
+		},
+		_ => None
+	}
+}
+
+/// The types which go through the vtable as one of the FFI types. References to trait
+/// objects must have elided lifetimes, since their wrappers only live as long as the call
+fn marshal(ty: &Type) -> Option<Marshal> {
+	match ty {
+		Type::Reference(x) => match &*x.elem {
+			Type::Path(elem) if elem.qself.is_none() && elem.path.is_ident("str") && x.mutability.is_none() => Some(Marshal::Str(x.lifetime.clone())),
+			Type::Slice(elem) if x.mutability.is_some() => Some(Marshal::SliceMut(x.lifetime.clone(), elem.elem.clone())),
+			Type::Slice(elem) => Some(Marshal::Slice(x.lifetime.clone(), elem.elem.clone())),
+			elem if x.lifetime.as_ref().is_none_or(|x| x.ident == "_") => {
+				let bound = dyn_bound(elem)?.clone();
+				Some(if x.mutability.is_some() { Marshal::DynMut(bound) } else { Marshal::DynRef(bound) })
+			},
+			_ => None
+		},
+		Type::Path(x) if x.qself.is_none() => {
+			let last = x.path.segments.last().unwrap();
+			match &last.arguments {
+				PathArguments::None if last.ident == "String" => Some(Marshal::String),
+				PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+					GenericArgument::Type(ty) if last.ident == "Vec" => Some(Marshal::Vec(Box::new(ty.clone()))),
+					GenericArgument::Type(ty) if last.ident == "Box" => Some(Marshal::DynBox(dyn_bound(ty)?.clone())),
+					// nullable pointers are FFI-safe in an `Option` already
+					GenericArgument::Type(ty) if last.ident == "Option" && (marshal(ty).is_some() || !is_nullable(ty)) => Some(Marshal::Option(Box::new(inner(ty)?))),
+					_ => None
+				},
+				PathArguments::AngleBracketed(args) if last.ident == "Result" && args.args.len() == 2 => match (&args.args[0], &args.args[1]) {
+					(GenericArgument::Type(ok), GenericArgument::Type(err)) => Some(Marshal::Result(Box::new(inner(ok)?), Box::new(inner(err)?))),
+					_ => None
+				},
+				_ => None
+			}
+		},
+		_ => None
+	}
+}
+
+/// A type inside an `Option` or `Result`. The wrappers of references to trait objects
+/// would only live as long as the closure converting them, so those aren't translated
+fn inner(ty: &Type) -> Option<Inner> {
+	match marshal(ty) {
+		Some(Marshal::DynRef(_) | Marshal::DynMut(_)) => None,
+		m => Some((ty.clone(), m))
+	}
+}
+
+/// Whether `Option<ty>` is a nullable pointer or integer
+fn is_nullable(ty: &Type) -> bool {
+	match ty {
+		Type::Reference(_) | Type::BareFn(_) => true,
+		Type::Path(x) => x.path.segments.last().is_some_and(|x| x.ident == "NonNull" || x.ident.to_string().starts_with("NonZero")),
+		_ => false
+	}
+}
+
+/// Whether a typed argument is actually a receiver like `self: Box<Self>`
+fn is_self_pat(pat: &Pat) -> bool {
+	matches!(pat, Pat::Ident(x) if x.ident == "self")
+}
+
+const INVALID_METHOD_OPTION: &str = "unknown option to an `ffi_trait` method, expected `on_panic = \"...\"`, `since = N` or `optional`";
+
+const INVALID_OPTION: &str = "unknown option to `ffi_trait`, expected `default_abi = \"...\"`, `no_default_abi`, `c_header`, `type_id`, `query_interface`, `on_panic = \"...\"`, `layout = \"...\"`, `iid = \"...\"`, `instantiate(...)` or `assoc(...)`";
+
+const INVALID_IMPL_OPTION: &str = "unknown option to `ffi_trait` on an impl, expected `provides(...)` or `type_id`";
+
+/// The 128-bit FNV-1a hash, which `ffi_trait::FFITypeId` uses too
+fn fnv(bytes: &[u8]) -> u128 {
+	let mut hash: u128 = 0x6c62272e07bb014262b821756295c58d;
+	for x in bytes {
+		hash ^= *x as u128;
+		hash = hash.wrapping_mul(0x0000000001000000000000000000013b);
+	}
+	hash
+}
+
+/// Parses a COM interface id like `"00000000-0000-0000-C000-000000000046"` into its bytes,
+/// in the order they're written
+fn guid_from_lit(x: Lit) -> Result<[u8; 16]> {
+	let err = |x| Error::new_spanned(x, "`iid` must be a GUID string, e.g. `iid = \"6b29fc40-ca47-1067-b31d-00dd010662da\"`");
+	let s = match &x {
+		Lit::Str(s) => s.value(),
+		_ => return Err(err(x))
+	};
+	let groups = s.split('-').map(|x| x.len()).collect::<Vec<_>>();
+	let hex = s.replace('-', "");
+	if groups != [8, 4, 4, 4, 12] || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+		return Err(err(x));
+	}
+	let mut out = [0; 16];
+	for (i, x) in out.iter_mut().enumerate() {
+		*x = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+	}
+	Ok(out)
+}
 
 fn abi_from_lit(x: LitStr) -> Abi {
 	Abi {
@@ -16,76 +430,481 @@ fn abi_from_lit(x: LitStr) -> Abi {
 	}
 }
 
+/// The options which list types, which `AttributeArgs` can't hold. Each
+/// keeps its keyword for errors
+#[derive(Default)]
+struct TypeOptions {
+	/// `instantiate(Trait<A>, ...)`
+	instantiate: Option<(Ident, Vec<Path>)>,
+	/// `assoc(Name = Type, ...)`
+	assoc: Option<(Ident, Vec<Binding>)>
+}
+
+/// Takes `instantiate(...)` and `assoc(...)` out of the options
+fn split_type_options(attr: proc_macro2::TokenStream) -> Result<(proc_macro2::TokenStream, TypeOptions)> {
+	use proc_macro2::{TokenTree, Delimiter};
+	use syn::parse::Parser;
+	use syn::punctuated::Punctuated;
+
+	let mut rest = proc_macro2::TokenStream::new();
+	let mut options = TypeOptions::default();
+	let tokens = attr.into_iter().collect::<Vec<_>>();
+	let mut i = 0;
+	while i < tokens.len() {
+		match (&tokens[i], tokens.get(i + 1)) {
+			(TokenTree::Ident(x), Some(TokenTree::Group(g))) if x == "instantiate" && g.delimiter() == Delimiter::Parenthesis => {
+				let paths = Punctuated::<Path, Token![,]>::parse_terminated.parse2(g.stream())?;
+				options.instantiate = Some((x.clone(), paths.into_iter().collect()));
+				// skip the comma after it too
+				i += 3;
+			},
+			(TokenTree::Ident(x), Some(TokenTree::Group(g))) if x == "assoc" && g.delimiter() == Delimiter::Parenthesis => {
+				let pins = Punctuated::<GenericArgument, Token![,]>::parse_terminated.parse2(g.stream())?;
+				let pins = pins.into_iter().map(|x| match x {
+					GenericArgument::Binding(x) => Ok(x),
+					x => Err(Error::new_spanned(x, "`assoc` takes a list of associated types and what they are, e.g. `assoc(Output = u32)`"))
+				}).collect::<Result<Vec<_>>>()?;
+				options.assoc = Some((x.clone(), pins));
+				i += 3;
+			},
+			(x, _) => {
+				rest.extend(Some(x.clone()));
+				i += 1;
+			}
+		}
+	}
+	Ok((rest, options))
+}
+
 #[proc_macro_attribute]
 pub fn ffi_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
+	let (attr, options) = match split_type_options(attr.into()) {
+		Ok(x) => x,
+		Err(e) => return e.to_compile_error().into()
+	};
+	let args = match syn::parse::Parser::parse2(syn::punctuated::Punctuated::<NestedMeta, Token![,]>::parse_terminated, attr) {
+		Ok(x) => x.into_iter().collect::<AttributeArgs>(),
+		Err(e) => return e.to_compile_error().into()
+	};
+	let result = match parse_macro_input!(item as Item) {
+		Item::Trait(def) => expand(args, options, def),
+		Item::Impl(def) => match options {
+			TypeOptions { instantiate: Some((x, _)), .. } => Err(Error::new_spanned(x, "`instantiate` goes on a generic trait, not on an impl")),
+			TypeOptions { assoc: Some((x, _)), .. } => Err(Error::new_spanned(x, "`assoc` goes on a trait, not on an impl")),
+			_ => expand_impl(args, def)
+		},
+		x => Err(Error::new_spanned(x, "`#[ffi_trait]` goes on a trait, or on an impl of one"))
+	};
+	result.unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+// `#[ffi_trait] impl Trait for Type` gives the associated constants of `Trait`,
+// which can't be in the trait itself since it wouldn't be object safe.
+// `#[ffi_trait(provides(A, B))]` also says which other ffi_traits `Type` can be
+// queried for, besides `Trait`, and `#[ffi_trait(type_id)]` puts the `StableTypeId`
+// of `Type` in the vtables of `#[ffi_trait(type_id)]` traits
+fn expand_impl(args: AttributeArgs, mut def: ItemImpl) -> Result<proc_macro2::TokenStream> {
+	let mut provides = None;
+	let mut type_id = None;
+	for arg in args {
+		match arg {
+			NestedMeta::Meta(Meta::Path(p)) if p.is_ident("type_id") => type_id = Some(p),
+			NestedMeta::Meta(Meta::List(l)) if l.path.is_ident("provides") => {
+				let provides = provides.get_or_insert_with(Vec::new);
+				for nested in l.nested {
+					match nested {
+						NestedMeta::Meta(Meta::Path(p)) => provides.push(p),
+						x => return Err(Error::new_spanned(x, "`provides` takes a list of ffi_traits, e.g. `provides(Seekable, Writer)`"))
+					}
+				}
+			},
+			x => return Err(Error::new_spanned(x, INVALID_IMPL_OPTION))
+		}
+	}
+	let own = match &def.trait_ {
+		Some((None, path, _)) => path.clone(),
+		_ => return Err(Error::new_spanned(&def.self_ty, "`#[ffi_trait]` goes on an impl of an ffi_trait"))
+	};
+	let mut consts_path = own.clone();
+	let last = consts_path.segments.last_mut().unwrap();
+	last.ident = Ident::new(&format!("__ffi_trait__{}__consts", last.ident), last.ident.span());
+	let (consts, items) = std::mem::take(&mut def.items).into_iter().partition::<Vec<_>, _>(|x| matches!(x, ImplItem::Const(_)));
+	def.items = items;
+	if let Some(p) = type_id {
+		def.items.push(parse_quote_spanned! {p.span()=>
+			extern "C" fn __ffi_trait__type_id() -> ffi_trait::FFITypeId {
+				<Self as ffi_trait::StableTypeId>::ID
+			}
+		});
+	}
+
+	let ty = &def.self_ty;
+	let (impl_generics, _, where_clause) = def.generics.split_for_impl();
+	let query = provides.map(|mut provides| {
+		provides.insert(0, own);
+		quote! {
+			unsafe impl #impl_generics ffi_trait::QueryInterface for #ty #where_clause {
+				fn query_interface(&self, id: ffi_trait::FFITypeId) -> ffi_trait::FFIDynPtr<dyn ffi_trait::FFIUnknown> {
+					#(
+						if id == <dyn #provides as ffi_trait::Interface>::ID {
+							return ffi_trait::__ffi_trait__provide::<dyn #provides + '_, Self>(self);
+						}
+					)*
+					ffi_trait::FFIDynPtr::null()
+				}
+			}
+		}
+	});
+	Ok(quote! {
+		#def
+
+		impl #impl_generics #consts_path for #ty #where_clause {
+			#(#consts)*
+		}
+
+		#query
+	})
+}
+
+/// Replaces the type parameters of a generic trait with the arguments of one of
+/// its instantiations
+fn substitute(tokens: proc_macro2::TokenStream, params: &[&Ident], args: &[Type]) -> proc_macro2::TokenStream {
+	tokens.into_iter().map(|x| match x {
+		proc_macro2::TokenTree::Ident(x) => match params.iter().position(|p| **p == x) {
+			Some(i) => {
+				let arg = &args[i];
+				quote!(#arg)
+			},
+			None => quote!(#x)
+		},
+		proc_macro2::TokenTree::Group(x) => {
+			let mut group = proc_macro2::Group::new(x.delimiter(), substitute(x.stream(), params, args));
+			group.set_span(x.span());
+			quote!(#group)
+		},
+		x => quote!(#x)
+	}).collect()
+}
+
+fn expand(args: AttributeArgs, options: TypeOptions, def: ItemTrait) -> Result<proc_macro2::TokenStream> {
 	let mut default_abi = Some(abi_from_lit(LitStr::new("C", Span::call_site().into())));
 	// outer none = not specified
 	// inner none = explicitly specified none
 	let mut set_default_abi: Option<Option<Abi>> = None;
+	let mut c_header = false;
+	let mut default_on_panic = None;
+	let mut type_id = false;
+	let mut query_interface = false;
+	let mut com = None;
+	let mut iid = None;
 
-	let args = parse_macro_input!(attr as AttributeArgs);
 	for arg in args {
 		match arg {
 			NestedMeta::Meta(m) => match m {
 				Meta::NameValue(nv) if nv.path.is_ident("default_abi") => {
 					if set_default_abi.is_some() {
-						panic!("conflicting options for default abi");
+						return Err(Error::new_spanned(nv.path, "conflicting options for the default ABI, use only one of `default_abi` and `no_default_abi`"));
 					}
 					set_default_abi = Some(Some(match nv.lit {
 						Lit::Str(x) => abi_from_lit(x),
-						_ => panic!("default abi must be a string literal")
+						x => return Err(Error::new_spanned(x, "the default ABI must be a string literal, e.g. `default_abi = \"C\"`"))
 					}));
 				},
 				Meta::Path(p) if p.is_ident("no_default_abi") => {
 					if set_default_abi.is_some() {
-						panic!("conflicting options for default abi");
+						return Err(Error::new_spanned(p, "conflicting options for the default ABI, use only one of `default_abi` and `no_default_abi`"));
 					}
 					set_default_abi = Some(None);
 				},
-				_ => panic!("invalid options to `ffi_trait`")
+				Meta::Path(p) if p.is_ident("c_header") => {
+					c_header = true;
+				},
+				Meta::Path(p) if p.is_ident("type_id") => {
+					type_id = true;
+				},
+				Meta::Path(p) if p.is_ident("query_interface") => {
+					query_interface = true;
+				},
+				Meta::NameValue(nv) if nv.path.is_ident("on_panic") => {
+					default_on_panic = on_panic_from_lit(nv.lit)?;
+				},
+				Meta::NameValue(nv) if nv.path.is_ident("layout") => {
+					com = match nv.lit {
+						Lit::Str(x) if x.value() == "ffi_trait" => None,
+						Lit::Str(x) if x.value() == "com" => Some(x),
+						x => return Err(Error::new_spanned(x, "`layout` must be \"ffi_trait\" or \"com\""))
+					};
+				},
+				Meta::NameValue(nv) if nv.path.is_ident("iid") => {
+					iid = Some((guid_from_lit(nv.lit)?, nv.path));
+				},
+				m => return Err(Error::new_spanned(m, INVALID_OPTION))
 			},
-			_ => panic!("invalid options to `ffi_trait`")
+			x => return Err(Error::new_spanned(x, INVALID_OPTION))
 		}
 	}
 
+	// COM methods use the calling convention of the system's COM, which is
+	// `__stdcall` on 32-bit Windows and the C one everywhere else
+	if com.is_some() {
+		default_abi = Some(abi_from_lit(LitStr::new("system", Span::call_site().into())));
+	}
 	if let Some(x) = set_default_abi {
 		default_abi = x;
 	}
+	// the COM vtable begins with `IUnknown` instead of the common part the other
+	// options extend, and a COM interface can only inherit from one other
+	if let Some(x) = &com {
+		if type_id || query_interface {
+			return Err(Error::new_spanned(x, "`layout = \"com\"` can't be used with `type_id` or `query_interface`, COM objects have their own `QueryInterface`"));
+		}
+		if !def.supertraits.is_empty() {
+			return Err(Error::new_spanned(&def.supertraits, "supertraits aren't supported with `layout = \"com\"`"));
+		}
+	} else if let Some((_, x)) = &iid {
+		return Err(Error::new_spanned(x, "`iid` is only used with `layout = \"com\"`"));
+	}
+
+	// generic traits get a vtable, an `FFITrait` impl and a C header for each type
+	// they're instantiated with, since a generic vtable can't be described to C
+	let mut tparams = Vec::new();
+	for param in &def.generics.params {
+		match param {
+			GenericParam::Type(x) if x.default.is_none() => {
+				// the vtables are statics, so the parameters can't borrow anything
+				let mut x = x.clone();
+				x.bounds.push(parse_quote!('static));
+				tparams.push(x);
+			},
+			x => return Err(Error::new_spanned(x, "only type parameters without defaults are supported on generic `ffi_trait`s"))
+		}
+	}
+	if let Some(x) = &def.generics.where_clause {
+		return Err(Error::new_spanned(x, "`where` clauses aren't supported in `ffi_trait`s, put the bounds on the type parameters instead"));
+	}
+	let generic = !tparams.is_empty();
+	let instances = match options.instantiate {
+		Some((x, _)) if !generic => return Err(Error::new_spanned(x, "`instantiate` is only used on generic traits")),
+		Some((_, x)) => x,
+		None if generic => return Err(Error::new_spanned(&def.generics, format!("generic `ffi_trait`s must list the types to generate them for, e.g. `#[ffi_trait(instantiate({}<u32>))]`", def.ident))),
+		None => Vec::new()
+	};
+	if generic {
+		if let Some(x) = &com {
+			return Err(Error::new_spanned(x, "`layout = \"com\"` traits can't be generic"));
+		}
+		if def.supertraits.iter().any(|x| !is_auto_trait(x)) {
+			return Err(Error::new_spanned(&def.supertraits, "generic `ffi_trait`s can't have supertraits other than `Send` and `Sync`"));
+		}
+	}
+	// the arguments of each instantiation, in the order of the parameters
+	let instances = instances.iter().map(|x| {
+		let last = x.segments.last().unwrap();
+		let args = match &last.arguments {
+			PathArguments::AngleBracketed(a) => a.args.iter().filter_map(|x| match x {
+				GenericArgument::Type(x) => Some(x.clone()),
+				_ => None
+			}).collect::<Vec<_>>(),
+			_ => Vec::new()
+		};
+		if last.ident != def.ident || args.len() != tparams.len() {
+			return Err(Error::new_spanned(x, format!("expected an instantiation of `{}` with {} type argument(s)", def.ident, tparams.len())));
+		}
+		Ok(args)
+	}).collect::<Result<Vec<_>>>()?;
+	let tparam_names = tparams.iter().map(|x| &x.ident).collect::<Vec<_>>();
+	let targs = if generic { quote!(<#(#tparam_names),*>) } else { quote!() };
+	let tparams_decl = quote!(#(#tparams,)*);
+
+	// associated constants are data in the vtable. they'd make the trait not object safe,
+	// so they go in a trait of their own, which `#[ffi_trait]` on an impl implements
+	let consts = def.items.iter().filter_map(|x| match x {
+		TraitItem::Const(x) => Some(x),
+		_ => None
+	}).collect::<Vec<_>>();
+	// the vtables are promoted to statics, which can't hold values of an unknown type
+	for x in &consts {
+		let ty = &x.ty;
+		if tparam_names.iter().any(|x| mentions_ident(quote!(#ty), &x.to_string())) {
+			return Err(Error::new_spanned(ty, "the types of constants in generic `ffi_trait`s can't use the type parameters"));
+		}
+	}
+	// associated types are only known for `Self: Sized`, so that `dyn Trait` doesn't
+	// name them. the vtable passes their pinned type or an `FFIDynBox` of their bound
+	let mut pins = options.assoc.as_ref().map(|(_, x)| x.iter().collect::<Vec<_>>()).unwrap_or_default();
+	let mut assoc = Vec::new();
+	for item in &def.items {
+		if let TraitItem::Type(x) = item {
+			if !x.generics.params.is_empty() || x.generics.where_clause.is_some() {
+				return Err(Error::new_spanned(&x.generics, "generic associated types aren't supported in `ffi_trait`s"));
+			}
+			let kind = match pins.iter().position(|p| p.ident == x.ident) {
+				Some(i) => Assoc::Pinned(Box::new(pins.remove(i).ty.clone())),
+				None => {
+					let mut bounds = x.bounds.iter().filter_map(|x| match x {
+						TypeParamBound::Trait(x) => Some(&x.path),
+						_ => None
+					});
+					match (bounds.next(), bounds.next()) {
+						(Some(bound), None) => Assoc::Boxed(bound.clone()),
+						_ => return Err(Error::new_spanned(x, format!("associated types of `ffi_trait`s must be pinned with `#[ffi_trait(assoc({} = ...))]` or bounded by a single ffi_trait", x.ident)))
+					}
+				}
+			};
+			assoc.push((x, kind));
+		}
+	}
+	if let Some(x) = pins.first() {
+		return Err(Error::new_spanned(&x.ident, format!("`{}` isn't an associated type of `{}`", x.ident, def.ident)));
+	}
+	if com.is_some() {
+		if let Some(x) = consts.first() {
+			return Err(Error::new_spanned(x, "`layout = \"com\"` traits can't have associated constants, COM vtables only hold methods"));
+		}
+		if let Some((x, _)) = assoc.first() {
+			return Err(Error::new_spanned(x, "`layout = \"com\"` traits can't have associated types, since `ComPtr` couldn't name them"));
+		}
+	}
+	let pinned = assoc.iter().filter_map(|(x, kind)| match kind {
+		Assoc::Pinned(ty) => Some((&x.ident, &**ty)),
+		_ => None
+	}).collect::<Vec<_>>();
+	let boxed_types = assoc.iter().filter_map(|(x, kind)| match kind {
+		Assoc::Boxed(bound) => Some((&x.ident, bound)),
+		_ => None
+	}).collect::<Vec<_>>();
+	// implementors must use the pinned types
+	let impl_bound = if pinned.is_empty() {
+		let name = &def.ident;
+		quote!(#name #targs)
+	} else {
+		let name = &def.ident;
+		let (pin_names, pin_tys): (Vec<_>, Vec<_>) = pinned.iter().cloned().unzip();
+		quote!(#name <#(#tparam_names,)* #(#pin_names = #pin_tys),*>)
+	};
 
-	let def: ItemTrait = syn::parse(item).unwrap();
+	let mut sigs = HashMap::new();
+	for item in &def.items {
+		if let TraitItem::Method(x) = item {
+			let sig = &x.sig;
+			let sig = parse2::<Signature>(substitute_assoc(quote!(#sig), &pinned))?;
+			let boxed = match &sig.output {
+				ReturnType::Type(_, ty) => boxed_types.iter().find(|(name, _)| is_self_assoc(ty, name)).map(|(_, bound)| (*bound).clone()),
+				ReturnType::Default => None
+			};
+			// the vtable can only give back an `FFIDynBox`, so they can't be taken or returned in anything else
+			let inputs = &sig.inputs;
+			let output = &sig.output;
+			let rest = if boxed.is_some() { quote!(#inputs) } else { quote!(#inputs #output) };
+			if let Some((name, _)) = boxed_types.iter().find(|(name, _)| mentions_self_assoc(rest.clone(), name)) {
+				return Err(Error::new_spanned(&x.sig, format!("`Self::{}` can only be returned by itself, since it's carried as an `FFIDynBox` through the vtable", name)));
+			}
+			// trait objects, strings, slices and vectors are passed as the FFI types of `ffi_trait`,
+			// unless the method uses the Rust ABI, which can pass them as they are
+			let marshal = |ty| if sig.abi.is_some() || default_abi.is_some() { marshal(ty) } else { None };
+			let inputs = sig.inputs.iter().filter_map(|x| match x {
+				FnArg::Typed(x) if !is_self_pat(&x.pat) => Some(match marshal(&x.ty) {
+					Some(m) => (m.raw(false), Some(m)),
+					None => ((*x.ty).clone(), None)
+				}),
+				_ => None
+			}).collect();
+			let marshal = match (&boxed, &sig.output) {
+				(None, ReturnType::Type(_, ty)) => match marshal(ty) {
+					// a wrapper would have nowhere to keep the `FFIDynRef` it got for the borrow
+					Some(Marshal::DynRef(bound) | Marshal::DynMut(bound)) => return Err(Error::new_spanned(ty, format!("references to trait objects can't be returned through the vtable, since the wrappers couldn't keep the object they point to. return an `FFIDynRef<dyn {0}>` or `FFIDynMut<dyn {0}>` instead", quote!(#bound)))),
+					x => x
+				},
+				_ => None
+			};
+			let output = match (&boxed, &marshal) {
+				(Some(bound), _) => parse_quote!(-> ffi_trait::FFIDynBox<dyn #bound>),
+				(_, Some(m)) => {
+					let ty = m.raw(true);
+					parse_quote!(-> #ty)
+				},
+				_ => sig.output.clone()
+			};
+			sigs.insert(x.sig.ident.clone(), VtableSig { sig, output, boxed_assoc: boxed, marshal, inputs });
+		}
+	}
 
-	let items = def.items.iter().map(|item| {
+	let items = def.items.iter().filter(|x| !matches!(x, TraitItem::Const(_) | TraitItem::Type(_))).map(|item| {
 		match item {
 			TraitItem::Method(x) => {
-				if x.sig.constness.is_some() {
-					panic!("`const` fns aren't supported in `ffi_trait`s");
-				}
-				if x.sig.asyncness.is_some() {
-					panic!("`async` fns aren't supported in `ffi_trait`s");
-				}
-				let name = &x.sig.ident;
-				let abi2 = &x.sig.abi;
-				let unsafety = &x.sig.unsafety;
-				let generics = &x.sig.generics;
-				let inputs2 = &x.sig.inputs;
-				let output = &x.sig.output;
+				if let Some(x) = x.sig.constness {
+					return Err(Error::new_spanned(x, "`const` fns aren't supported in `ffi_trait`s, remove the `const`"));
+				}
+				if let Some(x) = x.sig.asyncness {
+					return Err(Error::new_spanned(x, "`async` fns aren't supported in `ffi_trait`s, remove the `async`"));
+				}
+				let VtableSig { sig, output, boxed_assoc, marshal, inputs: rawtys } = &sigs[&x.sig.ident];
+				let name = &sig.ident;
+				let abi2 = &sig.abi;
+				let unsafety = &sig.unsafety;
+				let generics = &sig.generics;
+				let inputs2 = &sig.inputs;
+				let trait_output = &sig.output;
 				let block = &x.default;
-				if generics.where_clause.is_some() {
-					panic!("`where` clauses aren't supported in `ffi_trait`s");
+				if let Some(x) = &generics.where_clause {
+					return Err(Error::new_spanned(x, "`where` clauses aren't supported in `ffi_trait`s, put the bounds on the lifetime parameters instead"));
 				}
 
-				let lts2 = generics.params.iter().inspect(|param| {
-					match param {
-						GenericParam::Lifetime(_) => {},
-						_ => panic!("generics other than lifetimes aren't supported in `ffi_trait`s")
+				// `#[ffi_trait(...)]` on a method overrides the options of the trait
+				let mut on_panic = default_on_panic;
+				let mut since = None;
+				let mut optional = false;
+				let mut attrs = Vec::new();
+				for attr in &x.attrs {
+					if !attr.path.is_ident("ffi_trait") {
+						attrs.push(attr);
+						continue;
 					}
-				});
+					match attr.parse_meta() {
+						Ok(Meta::List(l)) => for nested in l.nested {
+							match nested {
+								NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("on_panic") => {
+									on_panic = on_panic_from_lit(nv.lit)?;
+								},
+								NestedMeta::Meta(Meta::Path(p)) if p.is_ident("optional") => {
+									optional = true;
+								},
+								NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("since") => {
+									since = Some(match nv.lit {
+										Lit::Int(x) => x.base10_parse::<u64>()?,
+										x => return Err(Error::new_spanned(x, "`since` must be an integer, e.g. `since = 2`"))
+									});
+								},
+								x => return Err(Error::new_spanned(x, INVALID_METHOD_OPTION))
+							}
+						},
+						Ok(x) => return Err(Error::new_spanned(x, INVALID_METHOD_OPTION)),
+						Err(e) => return Err(e)
+					}
+				}
+
+				for param in &generics.params {
+					if !matches!(param, GenericParam::Lifetime(_)) {
+						return Err(Error::new_spanned(param, "generics other than lifetimes aren't supported in `ffi_trait`s, since they aren't object safe. consider taking an FFI trait object instead"));
+					}
+				}
+				for arg in inputs2 {
+					if let FnArg::Typed(x) = arg {
+						if !is_self_pat(&x.pat) && is_self(&x.ty) {
+							return Err(Error::new_spanned(&x.ty, "`Self` arguments aren't supported in `ffi_trait`s, consider using an erased pointer such as `*mut ()`"));
+						}
+					}
+				}
 
 				let abi = abi2.as_ref().or(default_abi.as_ref());
-				let receiver2: RefCell<Option<&Receiver>> = RefCell::new(None);
+				let receiver2: RefCell<Option<&FnArg>> = RefCell::new(None);
 				let (inputs, t): (Vec<_>, Vec<_>) = inputs2.iter().enumerate().filter_map(|(index, arg)| match arg {
-					FnArg::Receiver(x) => {
-						*receiver2.borrow_mut() = Some(x);
+					FnArg::Receiver(_) => {
+						*receiver2.borrow_mut() = Some(arg);
+						None
+					},
+					FnArg::Typed(x) if is_self_pat(&x.pat) => {
+						*receiver2.borrow_mut() = Some(arg);
 						None
 					},
 					FnArg::Typed(x) => Some((x, (
@@ -99,138 +918,1086 @@ pub fn ffi_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
 								subpat: None
 							})),
 							colon_token: token::Colon { spans: [Span::call_site().into()] },
-							ty: {
-								fn check_self(ty: &Type) {
-									match ty {
-										Type::Path(x) => {
-											let s = &x.path.segments;
-											if s.len() == 1 && s[0].ident == "Self" {
-												panic!("`Self` arguments aren't supported in `ffi_trait`s. consider using erased pointers.");
-											}
-										},
-										_ => {}
-									}
-								}
-								check_self(&*x.ty);
-								x.ty.clone()
-							}
+							ty: x.ty.clone()
 						},
 						Ident::new(&format!("arg{}", index), Span::call_site().into())
 					)))
 				}).unzip();
-				let (rawinputs, rawnames): (Vec<_>, Vec<_>) = t.into_iter().unzip();
+				let (wrapinputs, rawnames): (Vec<_>, Vec<_>) = t.into_iter().unzip();
+				// the wrappers take the arguments like the trait does, and translate them
+				// to what the vtable takes
+				let rawinputs = wrapinputs.iter().zip(rawtys).map(|(x, (ty, _))| PatType { ty: Box::new(ty.clone()), ..x.clone() }).collect::<Vec<_>>();
+				let rawargs = rawnames.iter().zip(rawtys).map(|(x, (_, m))| match m {
+					Some(m) => m.wrap(quote!(#x)),
+					None => quote!(#x)
+				}).collect::<Vec<_>>();
+				let implargs = rawnames.iter().zip(rawtys).map(|(x, (_, m))| match m {
+					Some(m) => m.unwrap(x),
+					None => quote!(#x)
+				}).collect::<Vec<_>>();
 				let receiver = match *receiver2.borrow() {
 					Some(x) => x,
-					_ => panic!("`ffi_trait` methods must be object safe (all functions must take a self param, but `{}` doesn't)", name)
+					_ => return Err(Error::new_spanned(name, format!("`{}` must take `&self` or `&mut self`, since `ffi_trait`s must be object safe", name)))
 				};
 
-				let lts = lts2.collect::<Vec<_>>();
+				let lts = generics.params.iter().collect::<Vec<_>>();
+				// `Self::Name` is only known for `Self: Sized`
+				let sized = boxed_assoc.as_ref().map(|_| quote!(where Self: Sized));
 				let semicolon = if block.is_none() { quote!(;) } else { quote!() };
 
 				let tname = &def.ident;
-				let rawname = Ident::new(&format!("__ffi_trait__{}__raw_{}", tname, name), Span::def_site().into());
-				let rmut = receiver.mutability;
-				let rawmethod = if receiver.reference.is_some() {
-					quote!(unsafe #abi fn #rawname< Impl: #tname, #(#lts),* >(this: ::core::ptr::NonNull<()>, #(#rawinputs),*) #output {
-						<Impl as #tname>::#name(&#rmut *(this.as_ptr() as *mut Impl), #(#rawnames)*)
-					})
+				let tpath = quote!(#tname #targs);
+				let vtable_name = Ident::new(&format!("__ffi_trait__{}__vtable", tname), Span::call_site().into());
+				let rawname = Ident::new(&format!("__ffi_trait__{}__raw_{}", tname, name), Span::call_site().into());
+				// methods taking `self` or `self: Box<Self>` take ownership of the data, which
+				// only an `FFIDynBox` can give them
+				let (rmut, consumes, boxed) = match receiver {
+					FnArg::Receiver(x) => (x.mutability.filter(|_| x.reference.is_some()), x.reference.is_none(), false),
+					FnArg::Typed(x) if is_box_self(&x.ty) => (None, true, true),
+					FnArg::Typed(x) => return Err(Error::new_spanned(&x.ty, "the only typed receiver supported in `ffi_trait`s is `self: Box<Self>`"))
+				};
+				let nullable = optional || since.is_some();
+				if com.is_some() && (rmut.is_some() || consumes) {
+					return Err(Error::new_spanned(receiver, "methods of `layout = \"com\"` traits must take `&self`, since every `ComPtr` to the object shares it"));
+				}
+				if com.is_some() && nullable {
+					return Err(Error::new_spanned(name, "methods of `layout = \"com\"` traits can't be `optional` or have `since`, published COM interfaces are extended by new interfaces instead"));
+				}
+				if boxed_assoc.is_some() && nullable {
+					return Err(Error::new_spanned(name, "methods returning an associated type can't be `optional` or have `since`"));
+				}
+				if consumes && nullable {
+					return Err(Error::new_spanned(receiver, "methods taking `self` by value can't be `optional` or have `since`, since they couldn't give the data back if the method is missing"));
+				}
+				let call = if consumes {
+					let unbox = if boxed { None } else { Some(quote!(*)) };
+					quote!(<Impl as #tpath>::#name(#unbox ffi_trait::__ffi_trait__take_box!(this, Impl), #(#implargs),*))
 				} else {
-					panic!("`ffi_trait` methods cannot take `self` by value.");
+					quote!(<Impl as #tpath>::#name(&#rmut *(this.as_ptr() as *mut Impl), #(#implargs),*))
 				};
-
-				let methodimpl = quote!(fn #name <#(#lts),*> (#receiver, #(#rawinputs),*) #output {
-					unsafe {
-						#rawname(::core::mem::transmute(#receiver.data), #(#rawnames),*)
-					}
+				// a boxed associated type goes through the vtable as a trait object of its bound
+				let call = match (boxed_assoc, marshal) {
+					(Some(bound), _) => quote!(ffi_trait::FFIDynBox::<dyn #bound>::new(#call)),
+					(_, Some(m)) => m.wrap_output(call),
+					_ => call
+				};
+				// the wrappers return what the trait does
+				let wrapoutput = if marshal.is_some() { trait_output } else { output };
+				let rawret = match output {
+					ReturnType::Default => quote!(()),
+					ReturnType::Type(_, ty) => quote!(#ty)
+				};
+				let ret = match wrapoutput {
+					ReturnType::Default => quote!(()),
+					ReturnType::Type(_, ty) => quote!(#ty)
+				};
+				// the output of the vtable entry, and how the caller gets the method's output from it
+				let (rawoutput, body, unwrap) = match on_panic {
+					None => (quote!(#output), call, quote!(x)),
+					Some(OnPanic::Abort) => (quote!(#output), quote! {
+						let guard = ffi_trait::__ffi_trait__AbortOnUnwind;
+						let x = #call;
+						::core::mem::forget(guard);
+						x
+					}, quote!(x)),
+					Some(OnPanic::Catch) => (quote!(-> ffi_trait::FFIUnwindResult<#rawret>), quote! {
+						ffi_trait::__ffi_trait__catch!(#call)
+					}, quote! {
+						match x.into_option() {
+							Some(x) => x,
+							None => ::core::panic!("ffi_trait method `{}` panicked", stringify!(#name))
+						}
+					})
+				};
+				let unwrap = match marshal {
+					Some(m) => {
+						let x = Ident::new("x", Span::call_site().into());
+						let unwrapped = m.unwrap(&x);
+						quote!({
+							let x = #unwrap;
+							#unwrapped
+						})
+					},
+					None => unwrap
+				};
+				// the Rust ABI is unstable anyway, so only methods with an ABI are checked
+				let checked = if abi.is_some() {
+					rawtys.iter().map(|(ty, _)| ty).chain(match output {
+						ReturnType::Default => None,
+						ReturnType::Type(_, ty) => Some(&**ty)
+					}).collect()
+				} else { Vec::new() };
+				// the types of generic traits are only known for each instantiation, which checks them instead
+				let checks = checked.iter().filter(|_| !generic).map(|ty| quote_spanned! {ty.span()=>
+					ffi_trait::__ffi_trait__assert_ffi_safe::<#ty>();
+				});
+				// COM objects made by `ComPtr::new` hold the value after their header
+				let com_this = com.as_ref().map(|_| quote!(let this = ffi_trait::__ffi_trait__com_value::<Impl>(this);));
+				let rawmethod = quote!(unsafe #abi fn #rawname< #(#lts,)* #tparams_decl Impl: #impl_bound >(this: ::core::ptr::NonNull<()>, #(#rawinputs),*) #rawoutput {
+					#(#checks)*
+					#com_this
+					#body
 				});
 
-				let methodimpli = if rmut.is_none() {
-					Some(quote!(fn #name <#(#lts),*> (#receiver, #(#rawinputs),*) #output {
+				// forwards a call on an FFI wrapper type through the vtable, which
+				// might be embedded in the vtable of a subtrait
+				let tryname = Ident::new(&format!("try_{}", name), name.span());
+				let defaultname = Ident::new(&format!("__ffi_trait__default_{}", name), Span::call_site().into());
+				let methodimpl = if com.is_some() {
+					// `ComPtr` is the only wrapper of COM objects
+					quote!(#[allow(clippy::not_unsafe_ptr_arg_deref)] #unsafety fn #name <#(#lts),*> (#receiver, #(#wrapinputs),*) #wrapoutput {
+						unsafe {
+							let x = (self.vtable().#name)(self.as_raw(), #(#rawargs),*);
+							#unwrap
+						}
+					})
+				} else if nullable {
+					// a missing method falls back to the default body of the trait, if there is one
+					let missing = if block.is_some() {
+						quote!(<Self as #tpath>::#defaultname(self, #(#rawnames),*))
+					} else {
+						quote!(::core::panic!("ffi_trait method `{}` is missing from the vtable", stringify!(#name)))
+					};
+					// the vtable might come from an older version of the trait which doesn't have
+					// this method, so its size is checked before the slot is read
+					quote! {
+						#[allow(clippy::not_unsafe_ptr_arg_deref)] #unsafety fn #name <#(#lts),*> (#receiver, #(#wrapinputs),*) #wrapoutput {
+							#[allow(unused_unsafe)]
+							match unsafe { <Self as #tpath>::#tryname(self, #(#rawnames),*) } {
+								::core::option::Option::Some(x) => x,
+								::core::option::Option::None => #missing
+							}
+						}
+
+						#[allow(clippy::not_unsafe_ptr_arg_deref)] #unsafety fn #tryname <#(#lts),*> (#receiver, #(#wrapinputs),*) -> ::core::option::Option<#ret> {
+							unsafe {
+								let vtable = <__FFIDyn as ffi_trait::Upcast<dyn #tpath + '__ffi_dyn>>::upcast_vtable(self.vtable_ptr()).as_ptr();
+								let end = ::core::mem::offset_of!(#vtable_name #targs, #name) + ::core::mem::size_of::<::core::option::Option<fn()>>();
+								if ::core::ptr::addr_of!((*vtable).__ffi_trait__vtable_size).read() < end {
+									return ::core::option::Option::None;
+								}
+								let f = ::core::ptr::addr_of!((*vtable).#name).read()?;
+								let x = f(self.data(), #(#rawargs),*);
+								::core::option::Option::Some(#unwrap)
+							}
+						}
+					}
+				} else if consumes {
+					// the box gives up the data to the method
+					let (recv, unbox) = if boxed { (quote!(self: ffi_trait::__Box<Self>), quote!(*self)) } else { (quote!(self), quote!(self)) };
+					quote!(#[allow(clippy::not_unsafe_ptr_arg_deref)] #unsafety fn #name <#(#lts),*> (#recv, #(#wrapinputs),*) #wrapoutput {
 						unsafe {
-							#rawname(::core::mem::transmute(#receiver.data), #(#rawnames),*)
+							let this = ffi_trait::FFIDynBox::into_nonnull(#unbox);
+							let vtable = <__FFIDyn as ffi_trait::Upcast<dyn #tpath + '__ffi_dyn>>::upcast_vtable(this.vtable).as_ptr();
+							let x = ((*vtable).#name)(this.data, #(#rawargs),*);
+							#unwrap
 						}
-					}))
+					})
+				} else {
+					quote!(#[allow(clippy::not_unsafe_ptr_arg_deref)] #unsafety fn #name <#(#lts),*> (#receiver, #(#wrapinputs),*) #wrapoutput {
+						unsafe {
+							let vtable = <__FFIDyn as ffi_trait::Upcast<dyn #tpath + '__ffi_dyn>>::upcast_vtable(self.vtable_ptr()).as_ptr();
+							let x = ((*vtable).#name)(self.data(), #(#rawargs),*);
+							#unwrap
+						}
+					})
+				};
+
+				let fnty = quote!(for < #(#lts),* > unsafe #abi fn(::core::ptr::NonNull<()>, #(#rawinputs),*) #rawoutput);
+				// methods added in a later version of the trait may be missing from the vtable,
+				// so callers get a way to check for them
+				let trymethod = if nullable {
+					let doc = match since {
+						Some(since) => format!("Calls `{}` if the vtable has it, which it might not if it was made for a version of the trait before {}.", name, since),
+						None => format!("Calls `{}` if the vtable has it, which it might not if it was made by foreign code.", name)
+					};
+					// the default body, for the wrappers of vtables without the method
+					let default = block.as_ref().map(|block| quote! {
+						#[doc(hidden)]
+						#unsafety fn #defaultname< #(#lts),* >(#receiver, #(#inputs),*) #trait_output #block
+					});
+					Some(quote! {
+						#[doc = #doc]
+						#unsafety fn #tryname< #(#lts),* >(#receiver, #(#wrapinputs),*) -> ::core::option::Option<#ret> {
+							::core::option::Option::Some(<Self as #tpath>::#name(self, #(#rawnames),*))
+						}
+
+						#default
+					})
 				} else { None };
-				(
-					// vtable field
-					quote!(pub #name: for < #(#lts),* > unsafe #abi fn(::core::ptr::NonNull<()>, #(#rawinputs),*) #output),
-					(
-						// method
-						quote!(#unsafety fn #name< #(#lts),* >(#receiver, #(#inputs),*) #output #block #semicolon),
-						(rawmethod, (name, (rawname, (methodimpl, methodimpli))))
-					)
-				)
+				Ok(MethodParts {
+					fnty: if nullable { quote!(::core::option::Option<#fnty>) } else { fnty },
+					method: quote!(#(#attrs)* #unsafety fn #name< #(#lts),* >(#receiver, #(#inputs),*) #trait_output #sized #block #semicolon #trymethod),
+					rawmethod,
+					name,
+					rawname,
+					methodimpl,
+					mutable: rmut.is_some(),
+					consumes,
+					since,
+					nullable,
+					checked: checked.into_iter().cloned().collect(),
+					cmethod: header::CMethod {
+						name,
+						inputs: rawtys.iter().map(|(ty, _)| ty).collect(),
+						output,
+						callable: abi.is_some(),
+						system: abi.and_then(|x| x.name.as_ref()).is_some_and(|x| x.value() == "system"),
+						catch: matches!(on_panic, Some(OnPanic::Catch)),
+						consumes,
+						since,
+						optional
+					}
+				})
 			},
-			_ => panic!("Only methods are supported in `ffi_trait`s")
+			x => Err(Error::new_spanned(x, "only methods, constants and associated types are supported in `ffi_trait`s, consider moving this out of the trait"))
 		}
 	});
-	let (fields, t): (Vec<_>, Vec<_>) = items.unzip();
-	let (methods, t): (Vec<_>, Vec<_>) = t.into_iter().unzip();
-	let (rawmethods, t): (Vec<_>, Vec<_>) = t.into_iter().unzip();
-	let (methodnames, t): (Vec<_>, Vec<_>) = t.into_iter().unzip();
-	let (rawnames, t): (Vec<_>, Vec<_>) = t.into_iter().unzip();
-	let (methodimpls, methodimplsi): (Vec<_>, Vec<_>) = t.into_iter().unzip();
+	// report the errors of every method at once
+	let mut items2 = Vec::new();
+	let mut errors: Option<Error> = None;
+	for x in items {
+		match x {
+			Ok(x) => items2.push(x),
+			Err(e) => match &mut errors {
+				Some(x) => x.combine(e),
+				None => errors = Some(e)
+			}
+		}
+	}
+	if let Some(e) = errors {
+		return Err(e);
+	}
+	let items = items2;
+
+	// the vtable can only grow at the end, so methods with `since` come last and in order
+	let mut last_since = None;
+	for x in &items {
+		match (last_since, x.since) {
+			(Some(_), None) => return Err(Error::new_spanned(x.name, "methods without `since` must come before those with `since`, since new methods are added at the end of the vtable")),
+			(Some(a), Some(b)) if b < a => return Err(Error::new_spanned(x.name, format!("`since = {}` comes after `since = {}`, methods must be in the order they were added", b, a))),
+			_ => last_since = x.since.or(last_since)
+		}
+	}
+	let requiredmethods = items.iter().filter(|x| !x.nullable).map(|x| x.name).collect::<Vec<_>>();
+	let fntys = items.iter().map(|x| &x.fnty).collect::<Vec<_>>();
+	// methods with `since` or `optional` are already nullable in the vtable
+	let builderfntys = items.iter().map(|x| {
+		let fnty = &x.fnty;
+		if x.nullable { quote!(#fnty) } else { quote!(::core::option::Option<#fnty>) }
+	}).collect::<Vec<_>>();
+	let methods = items.iter().map(|x| &x.method);
+	let rawmethods = items.iter().map(|x| &x.rawmethod);
+	let methodnames = items.iter().map(|x| x.name).collect::<Vec<_>>();
+	let rawvalues = items.iter().map(|x| {
+		let rawname = &x.rawname;
+		if x.nullable { quote!(Some(#rawname::<#(#tparam_names,)* __FFIImpl>)) } else { quote!(#rawname::<#(#tparam_names,)* __FFIImpl>) }
+	}).collect::<Vec<_>>();
+	let methodimpls = items.iter().map(|x| &x.methodimpl).collect::<Vec<_>>();
+
+	// supertraits must be `ffi_trait`s too. their vtables are embedded in ours
+	// so that we can be upcasted to them
+	let supertraits = def.supertraits;
+	let supers = supertraits.iter().filter(|x| !is_auto_trait(x)).filter_map(|x| match x {
+		TypeParamBound::Trait(x) => Some(&x.path),
+		_ => None
+	}).collect::<Vec<_>>();
+	let autos = supertraits.iter().filter(|x| is_auto_trait(x)).collect::<Vec<_>>();
+	let superfields = supers.iter().map(|x| {
+		let last = &x.segments.last().unwrap().ident;
+		Ident::new(&format!("__ffi_trait__super_{}", last), Span::call_site().into())
+	}).collect::<Vec<_>>();
+	let superbound = if supertraits.is_empty() { None } else { Some(quote!(+ #supertraits)) };
+	// only the supertraits listed can be upcast to, so they must include the supertraits
+	// of each of them. these point at the one whose supertraits are missing
+	let ancestor_checks = if supers.is_empty() { None } else {
+		let ident = &def.ident;
+		let checks = supers.iter().map(|x| quote_spanned! {x.span()=>
+			ffi_trait::__ffi_trait__assert_ancestors::<dyn #ident, dyn #x>();
+		});
+		// their vtables are embedded in the middle of ours, so they can't grow
+		let since_checks = supers.iter().map(|x| {
+			let message = format!("`{}` has methods with `since`, so it can't be a supertrait: adding them would move everything after its vtable in those of its subtraits", quote!(#x));
+			quote_spanned! {x.span()=>
+				const _: () = ::core::assert!(!<dyn #x as ffi_trait::__ffi_trait__Since>::SINCE, #message);
+			}
+		});
+		Some(quote! {
+			const _: () = {
+				#[allow(dead_code)]
+				fn check() {
+					#(#checks)*
+				}
+			};
+
+			#(#since_checks)*
+		})
+	};
 
 	let name   = def.ident;
 	let vis    = def.vis;
+
+	let supernames = supers.iter().map(|x| &x.segments.last().unwrap().ident).collect::<Vec<_>>();
 	let vtable = Ident::new(&format!("__ffi_trait__{}__vtable", name), Span::call_site().into());
+
+	// a trait which isn't generic is its only instantiation
+	let instances = if generic { instances } else { vec![Vec::new()] };
+	let mut inst_targs = Vec::new();
+	let mut inst_ids = Vec::new();
+	let mut inst_headers = Vec::new();
+	let mut inst_checks = Vec::new();
+	let mut com_iid = [0; 16];
+	for args in &instances {
+		let subst = |x: proc_macro2::TokenStream| substitute(x, &tparam_names, args);
+		// instantiations are told apart in C by their arguments, e.g. `Sink_u32`
+		let cname = if generic {
+			Ident::new(&header::mangle(&quote!(#name <#(#args),*>).to_string()), name.span())
+		} else {
+			name.clone()
+		};
+		let inputs = items.iter().map(|x| x.cmethod.inputs.iter().map(|ty| parse2::<Type>(subst(quote!(#ty)))).collect::<Result<Vec<_>>>()).collect::<Result<Vec<_>>>()?;
+		let outputs = items.iter().map(|x| {
+			let output = x.cmethod.output;
+			parse2::<ReturnType>(subst(quote!(#output)))
+		}).collect::<Result<Vec<_>>>()?;
+		let cmethods = items.iter().zip(&inputs).zip(&outputs).map(|((x, inputs), output)| header::CMethod {
+			inputs: inputs.iter().collect(),
+			output,
+			..x.cmethod
+		}).collect::<Vec<_>>();
+		let cmethods = cmethods.iter().collect::<Vec<_>>();
+		let const_tys = consts.iter().map(|x| {
+			let ty = &x.ty;
+			parse2::<Type>(subst(quote!(#ty)))
+		}).collect::<Result<Vec<_>>>()?;
+		let cconsts = consts.iter().map(|x| &x.ident).zip(&const_tys).collect::<Vec<_>>();
+
+		// the crate name keeps traits of the same name apart, and unlike the module path
+		// it's known here, so that the id can go in the C header
+		let interface_id = fnv(format!("{}::{}", std::env::var("CARGO_PKG_NAME").unwrap_or_default(), cname).as_bytes());
+		// COM interface ids are made from the interface id unless one is given,
+		// marked as a version 8 (custom) UUID
+		com_iid = match &iid {
+			Some((x, _)) => *x,
+			None => {
+				let mut x = interface_id.to_be_bytes();
+				x[6] = (x[6] & 0x0f) | 0x80;
+				x[8] = (x[8] & 0x3f) | 0x80;
+				x
+			}
+		};
+		let header = if com.is_some() {
+			header::generate_com(&cname, &cmethods, com_iid)
+		} else {
+			header::generate(&cname, &supernames, &cconsts, &cmethods, type_id, query_interface, interface_id)
+		};
+		if c_header {
+			// only crates with a build script get an OUT_DIR
+			let dir = std::env::var("OUT_DIR").map_err(|_| Error::new_spanned(&name, "`c_header` needs the `OUT_DIR` of a build script, add a `build.rs` to the crate (an empty `fn main() {}` will do)"))?;
+			let path = std::path::Path::new(&dir).join(format!("{}.h", cname));
+			// don't touch the file if nothing changed, so C builds aren't redone
+			if std::fs::read_to_string(&path).ok().as_ref() != Some(&header) {
+				std::fs::write(&path, &header).map_err(|e| Error::new_spanned(&name, format!("couldn't write `{}`: {}", path.display(), e)))?;
+			}
+		}
+
+		// the raw methods of generic traits can't check their types, so each instantiation
+		// does. the types of the constants are checked here too
+		let checked = items.iter().flat_map(|x| &x.checked).filter(|_| generic).chain(consts.iter().map(|x| &x.ty)).collect::<Vec<_>>();
+		if !checked.is_empty() {
+			let checks = checked.iter().map(|ty| {
+				let checked = subst(quote!(#ty));
+				quote_spanned! {ty.span()=>
+					ffi_trait::__ffi_trait__assert_ffi_safe::<#checked>();
+				}
+			});
+			inst_checks.push(quote! {
+				const _: () = {
+					#(#checks)*
+				};
+			});
+		}
+		inst_targs.push(if generic { quote!(<#(#args),*>) } else { quote!() });
+		inst_ids.push(interface_id);
+		inst_headers.push(header);
+	}
+	let builder = Ident::new(&format!("__ffi_trait__{}__vtable_builder", name), Span::call_site().into());
 	let dyn_vt = Ident::new(&format!("__ffi_trait__{}__dyn_vtable", name), Span::call_site().into());
-	let x = (quote! {
+
+	// implemented by `#[ffi_trait]` on an impl, which is also used on impls of traits without constants
+	let consts_trait = Ident::new(&format!("__ffi_trait__{}__consts", name), Span::call_site().into());
+	let consts_message = format!("`{{Self}}` doesn't give the associated constants of `{}`", name);
+	let consts_def = quote! {
+		#[doc(hidden)]
+		#[diagnostic::on_unimplemented(
+			message = #consts_message,
+			note = "add `#[ffi_trait]` to its impl of the trait, which takes the constants out of the impl"
+		)]
+		#vis trait #consts_trait <#tparams_decl> {
+			#(#consts)*
+		}
+	};
+
+	// COM objects are thin pointers to a struct beginning with the vtable pointer,
+	// whose vtable begins with `IUnknown`. they're only used through `ComPtr`
+	if com.is_some() {
+		let header = &inst_headers[0];
+		let iid = com_iid;
+		let data1 = u32::from_be_bytes([iid[0], iid[1], iid[2], iid[3]]);
+		let data2 = u16::from_be_bytes([iid[4], iid[5]]);
+		let data3 = u16::from_be_bytes([iid[6], iid[7]]);
+		let data4 = &iid[8..];
+		return Ok(quote! {
+			// only objects made by `ComPtr::new` use these
+			ffi_trait::__ffi_trait__if_boxed! {
+				#(#rawmethods)*
+			}
+
+			#vis trait #name {
+				#(#methods)*
+			}
+
+			#consts_def
+
+			#[repr(C)]
+			#[derive(Debug, Copy, Clone)]
+			#vis struct #vtable {
+				pub __ffi_trait__unknown: ffi_trait::IUnknownVtable,
+				#(pub #methodnames: #fntys),*
+			}
+
+			unsafe impl ffi_trait::ComVtableLayout for #vtable {}
+			#[diagnostic::do_not_recommend]
+			unsafe impl ffi_trait::FfiSafe for #vtable {}
+
+			unsafe impl ffi_trait::ComInterface for dyn #name {
+				type Vtable = #vtable;
+				const IID: ffi_trait::Guid = ffi_trait::Guid { data1: #data1, data2: #data2, data3: #data3, data4: [#(#data4),*] };
+			}
+
+			// `ComPtr` has no lifetime, so the object can't borrow anything
+			ffi_trait::__ffi_trait__if_boxed! {
+				unsafe impl<__FFIImpl: #name + 'static> ffi_trait::ComVtableFor<__FFIImpl> for dyn #name {
+					const VTABLE: &'static #vtable = &#vtable {
+						__ffi_trait__unknown: ffi_trait::IUnknownVtable::new::<dyn #name, __FFIImpl>(),
+						#(#methodnames: #rawvalues),*
+					};
+				}
+			}
+
+			impl ffi_trait::CHeader for dyn #name {
+				const C_HEADER: &'static str = #header;
+			}
+
+			impl #name for ffi_trait::ComPtr<dyn #name> {
+				#(#methodimpls)*
+			}
+		});
+	}
+
+	let tpath = quote!(#name #targs);
+	let consts_bound = if consts.is_empty() { None } else { Some(quote!(+ #consts_trait #targs)) };
+	let const_names = consts.iter().map(|x| &x.ident).collect::<Vec<_>>();
+	let const_tys = consts.iter().map(|x| &x.ty).collect::<Vec<_>>();
+	// the constants of a builder start out as their defaults in the trait, unless
+	// those depend on the implementor
+	let const_defaults = consts.iter().map(|x| match &x.default {
+		Some((_, expr)) if !mentions_ident(quote!(#expr), "Self") => quote!(#expr),
+		_ => quote!(::core::default::Default::default())
+	});
+	let assoc_decls = assoc.iter().map(|(x, kind)| {
+		let attrs = &x.attrs;
+		let ident = &x.ident;
+		let mut bounds = x.bounds.clone();
+		// it's boxed as a `dyn Bound + 'static`
+		if let Assoc::Boxed(_) = kind {
+			bounds.push(parse_quote!('static));
+		}
+		let colon = if bounds.is_empty() { None } else { Some(quote!(:)) };
+		quote!(#(#attrs)* type #ident #colon #bounds where Self: Sized;)
+	});
+	let assoc_impls = assoc.iter().map(|(x, kind)| {
+		let ident = &x.ident;
+		match kind {
+			Assoc::Pinned(ty) => quote!(type #ident = #ty;),
+			Assoc::Boxed(bound) => quote!(type #ident = ffi_trait::FFIDynBox<dyn #bound>;)
+		}
+	}).collect::<Vec<_>>();
+	// the impls which are generic over the parameters only hold for the instantiations
+	let is_ffi = if generic {
+		quote!(for<'__ffi_any> dyn #tpath + '__ffi_any: ffi_trait::FFITrait<Vtable = #vtable #targs>,)
+	} else { quote!() };
+	// derived impls would require the parameters to be `Clone` too
+	let (derives, marker, marker_value, copy_impls) = if generic {
+		(quote!(#[derive(Debug)]), Some(quote! {
+			pub __ffi_trait__marker: ::core::marker::PhantomData<fn(#(#tparam_names),*)>,
+		}), Some(quote! {
+			__ffi_trait__marker: ::core::marker::PhantomData,
+		}), Some(quote! {
+			impl<#tparams_decl> ::core::marker::Copy for #vtable #targs {}
+			impl<#tparams_decl> ::core::clone::Clone for #vtable #targs {
+				fn clone(&self) -> Self { *self }
+			}
+
+			impl<#tparams_decl> ::core::marker::Copy for #builder #targs {}
+			impl<#tparams_decl> ::core::clone::Clone for #builder #targs {
+				fn clone(&self) -> Self { *self }
+			}
+		}))
+	} else { (quote!(#[derive(Debug, Copy, Clone)]), None, None, None) };
+
+	let has_since = items.iter().any(|x| x.since.is_some());
+	// the part of the vtable which every version of the trait has
+	let required_size = match items.iter().find(|x| x.since.is_some()) {
+		Some(x) => {
+			let first = x.name;
+			quote!(::core::mem::offset_of!(#vtable #targs, #first))
+		},
+		None => quote!(::core::mem::size_of::<#vtable #targs>())
+	};
+
+	// the vtables made here point to the concrete type's `__ffi_trait__into_std`, so that a box
+	// of it can become the `Box<dyn Trait>` it was. the lifetime is erased to fit in the vtable
+	let rust_field = quote! {
+		pub __ffi_trait__rust: ::core::option::Option<&'static ffi_trait::__ffi_trait__RustVtable<
+			unsafe fn(::core::ptr::NonNull<()>) -> *mut (dyn #tpath + 'static)
+		>>,
+	};
+	let rust_method = quote! {
+		#[doc(hidden)]
+		unsafe fn __ffi_trait__into_std<'__ffi_dyn>(data: ::core::ptr::NonNull<()>) -> *mut (dyn #tpath + '__ffi_dyn)
+		where Self: Sized + '__ffi_dyn {
+			data.as_ptr() as *mut Self
+		}
+	};
+	// rebuilds the original box if this binary made it, otherwise boxes the wrapper
+	let into_std = |m: &proc_macro2::TokenStream| quote! {
+		unsafe {
+			match ::core::ptr::addr_of!((*x.vtable_ptr().as_ptr()).__ffi_trait__rust).read() {
+				Some(rust) if ::core::ptr::eq(rust.binary, &ffi_trait::__ffi_trait__BINARY) => {
+					let data = ffi_trait::FFIDynBox::into_nonnull(x).data;
+					ffi_trait::__Box::from_raw(::core::mem::transmute::<
+						*mut (dyn #tpath + 'static),
+						*mut (dyn #tpath #m + '__ffi_dyn)
+					>((rust.into_std)(data)))
+				},
+				_ => ffi_trait::__Box::new(x)
+			}
+		}
+	};
+
+	// `type_id` adds a slot identifying the concrete type after the common part of the vtable.
+	// it calls a hidden method of the trait, which returns zeros unless the impl opted in
+	// with `#[ffi_trait(type_id)]`, so implementors don't all need a stable type id
+	let (type_id_field, type_id_value, type_id_default, type_id_method, downcast) = if type_id {
+		(Some(quote! {
+			pub __ffi_trait__type_id: ::core::option::Option<unsafe extern "C" fn() -> ffi_trait::FFITypeId>,
+		}), Some(quote! {
+			__ffi_trait__type_id: Some(<__FFIImpl as #tpath>::__ffi_trait__type_id),
+		}), Some(quote! {
+			__ffi_trait__type_id: None,
+		}), Some(quote! {
+			#[doc(hidden)]
+			extern "C" fn __ffi_trait__type_id() -> ffi_trait::FFITypeId where Self: Sized {
+				ffi_trait::FFITypeId::__ffi_trait__NONE
+			}
+		}), Some(quote! {
+			unsafe impl<'__ffi_dyn, #tparams_decl> ffi_trait::Downcast for dyn #tpath + '__ffi_dyn where #is_ffi {
+				fn type_id(vt: &#vtable #targs) -> ::core::option::Option<ffi_trait::FFITypeId> {
+					vt.__ffi_trait__type_id.map(|f| unsafe { f() }).filter(|x| *x != ffi_trait::FFITypeId::__ffi_trait__NONE)
+				}
+			}
+		}))
+	} else { (None, None, None, None, None) };
+
+	// `query_interface` adds a slot after it, which asks the object for other interfaces.
+	// every implementor must say which ones it provides
+	let (query_field, query_value, query_default, query_fn, query_bound, query_note) = if query_interface {
+		(Some(quote! {
+			pub __ffi_trait__query_interface: ::core::option::Option<ffi_trait::QueryInterfaceFn>,
+		}), Some(quote! {
+			__ffi_trait__query_interface: Some(ffi_trait::__ffi_trait__query_interface::<__FFIImpl>),
+		}), Some(quote! {
+			__ffi_trait__query_interface: None,
+		}), quote! {
+			fn query_interface_fn(vt: &Self::Vtable) -> ::core::option::Option<ffi_trait::QueryInterfaceFn> {
+				vt.__ffi_trait__query_interface
+			}
+		}, Some(quote!(+ ffi_trait::QueryInterface)), Some((
+			"doesn't say which interfaces it provides",
+			"add `#[ffi_trait(provides(...))]` to its impl of the trait, listing the other ffi_traits it can be queried for"
+		)))
+	} else { (None, None, None, quote!(), None, None) };
+
+	// explains the bound above when an implementor lacks it
+	let bound_note = query_note.map(|(message, note)| {
+		let message = format!("`{{Self}}` {}", message);
+		quote!(#[diagnostic::on_unimplemented(message = #message, note = #note)])
+	});
+
+	let id_high = inst_ids.iter().map(|x| (x >> 64) as u64).collect::<Vec<_>>();
+	let id_low = inst_ids.iter().map(|x| *x as u64).collect::<Vec<_>>();
+
+	// every wrapper implements the trait by calling through the vtable
+	let wrapper = |params: proc_macro2::TokenStream, ty: proc_macro2::TokenStream| {
+		// the constants of the wrapped object are only known at runtime, from its vtable
+		let consts_impl = consts_bound.as_ref().map(|_| quote! {
+			impl<#params #tparams_decl __FFIDyn> #consts_trait #targs for #ty
+			where #is_ffi __FFIDyn: ffi_trait::Upcast<dyn #tpath + '__ffi_dyn> + ?Sized {
+				#(const #const_names: #const_tys = ::core::panic!("the constants of an ffi_trait wrapper are only in its vtable");)*
+			}
+		});
+		quote! {
+			impl<#params #tparams_decl __FFIDyn> #tpath for #ty
+			where #is_ffi __FFIDyn: ffi_trait::Upcast<dyn #tpath + '__ffi_dyn> + ?Sized, Self: #(#supers +)* #(#autos +)* {
+				#(#assoc_impls)*
+				#(#methodimpls)*
+			}
+
+			#consts_impl
+		}
+	};
+
+	// a shared reference can only implement the trait if no method needs `&mut self`,
+	// and only a box can if any method takes `self` by value
+	let consuming = items.iter().any(|x| x.consumes);
+	let refimpl = if !consuming && items.iter().all(|x| !x.mutable) {
+		let r = wrapper(quote!('__ffi_ref, '__ffi_dyn,), quote!(ffi_trait::FFIDynRef<'__ffi_ref, __FFIDyn>));
+		let thin = wrapper(quote!('__ffi_ref, '__ffi_dyn,), quote!(ffi_trait::FFIDynThinRef<'__ffi_ref, __FFIDyn>));
+		let arc = wrapper(quote!('__ffi_dyn,), quote!(ffi_trait::FFIDynArc<__FFIDyn>));
+		let rc = wrapper(quote!('__ffi_dyn,), quote!(ffi_trait::FFIDynRc<__FFIDyn>));
+		Some(quote! {
+			#r
+			#thin
+
+			// shared handles give the same access as a shared reference
+			ffi_trait::__ffi_trait__if_boxed! {
+				#arc
+				#rc
+			}
+		})
+	} else { None };
+	let mutimpl = if !consuming {
+		let m = wrapper(quote!('__ffi_ref, '__ffi_dyn,), quote!(ffi_trait::FFIDynMut<'__ffi_ref, __FFIDyn>));
+		let thin = wrapper(quote!('__ffi_dyn,), quote!(ffi_trait::FFIDynThinBox<__FFIDyn>));
+		Some(quote! {
+			#m
+
+			// the data of a thin box is owned like that of a box, but it can't be
+			// taken out for methods taking `self` without moving it
+			ffi_trait::__ffi_trait__if_boxed! {
+				#thin
+			}
+		})
+	} else { None };
+	let boximpl = wrapper(quote!('__ffi_dyn,), quote!(ffi_trait::FFIDynBox<__FFIDyn>));
+	// how a `&dyn Trait` argument is passed on, through a wrapper that implements the trait
+	// and is `Send` and `Sync` like the object, which `FFIDynRef` isn't. bounded like `into_box`
+	let as_dyn = |m: &proc_macro2::TokenStream, is_ffi_m: &proc_macro2::TokenStream| {
+		let (body, wrapper, boxed) = if consuming {
+			(quote!(ffi_trait::FFIDynRef::__ffi_trait__as_box(x)), quote!(ffi_trait::FFIDynBox<dyn #tpath #m + '__ffi_dyn>), true)
+		} else {
+			(quote!(ffi_trait::FFIDynRef::__ffi_trait__as_mut(x)), quote!(ffi_trait::FFIDynMut<'__ffi_ref, dyn #tpath #m + '__ffi_dyn>), false)
+		};
+		let imp = quote! {
+			impl<'__ffi_ref, '__ffi_dyn: '__ffi_ref, #tparams_decl> ffi_trait::__ffi_trait__AsDyn<'__ffi_ref> for dyn #tpath #m + '__ffi_dyn
+			where #is_ffi #is_ffi_m #wrapper: #tpath {
+				type Dyn = dyn #tpath #m + '__ffi_ref;
+				fn __ffi_trait__as_dyn<'__ffi_borrow>(x: &'__ffi_borrow ffi_trait::FFIDynRef<'__ffi_ref, Self>) -> &'__ffi_borrow Self::Dyn {
+					#body
+				}
+			}
+		};
+		if boxed { quote!(ffi_trait::__ffi_trait__if_boxed! { #imp }) } else { imp }
+	};
+	// a wrapper can't be made into a `dyn Trait` if the trait has constants, since
+	// its vtable would need the constants of the object it holds
+	// bounded so that a missing supertrait is only reported by `ancestor_checks`
+	let from_body = into_std(&quote!());
+	let into_box = consts_bound.is_none().then(|| quote! {
+		impl<'__ffi_dyn, #tparams_decl> ::core::convert::From<ffi_trait::FFIDynBox<dyn #tpath + '__ffi_dyn>> for ffi_trait::__Box<dyn #tpath + '__ffi_dyn>
+		where #is_ffi ffi_trait::FFIDynBox<dyn #tpath + '__ffi_dyn>: #tpath {
+			fn from(x: ffi_trait::FFIDynBox<dyn #tpath + '__ffi_dyn>) -> Self {
+				#from_body
+			}
+		}
+	});
+
+	// `dyn Trait + Send` and the like are FFI traits of their own with the same vtable,
+	// which only hold implementors that are `Send`, so that their wrappers are too
+	let send = quote!(+ ::core::marker::Send);
+	let sync = quote!(+ ::core::marker::Sync);
+	let combos = [quote!(), send.clone(), sync.clone(), quote!(#send #sync)];
+	let mut auto_impls = vec![as_dyn(&quote!(), &quote!())];
+	for (i, m) in combos.iter().enumerate().skip(1) {
+		let is_ffi_m = if generic {
+			quote!(for<'__ffi_any> dyn #tpath #m + '__ffi_any: ffi_trait::FFITrait<Vtable = #vtable #targs>,)
+		} else { quote!() };
+		auto_impls.push(as_dyn(m, &is_ffi_m));
+		for targs in &inst_targs {
+			auto_impls.push(quote! {
+				impl<'__ffi_dyn> ffi_trait::FFITrait for dyn #name #targs #m + '__ffi_dyn {
+					type Vtable = #vtable #targs;
+					#query_fn
+				}
+			});
+		}
+		auto_impls.push(quote! {
+			unsafe impl<'__ffi_dyn, #tparams_decl> ffi_trait::VtableBuilder for dyn #tpath #m + '__ffi_dyn where #is_ffi #is_ffi_m {
+				type Builder = #builder #targs;
+
+				unsafe fn validate_ptr(b: ::core::ptr::NonNull<#builder #targs>) -> ::core::result::Result<(), ffi_trait::VtableError> {
+					<dyn #tpath + '__ffi_dyn as ffi_trait::VtableBuilder>::validate_ptr(b)
+				}
+			}
+
+			unsafe impl<'__ffi_dyn, #tparams_decl __FFIImpl: #impl_bound #query_bound #consts_bound #m + '__ffi_dyn> ffi_trait::VtableFor<__FFIImpl> for dyn #tpath #m + '__ffi_dyn where #is_ffi #is_ffi_m {
+				const VTABLE: &'static #vtable #targs = <dyn #tpath + '__ffi_dyn as ffi_trait::VtableFor<__FFIImpl>>::VTABLE;
+			}
+
+			#[diagnostic::do_not_recommend]
+			impl<'__ffi_dyn, #tparams_decl> ffi_trait::IntoTraitObjectRuntime<dyn #tpath #m + '__ffi_dyn> for dyn #tpath #m + '__ffi_dyn where #is_ffi #is_ffi_m {
+				fn get_vt(&self) -> &#vtable #targs {
+					<Self as #dyn_vt #targs>::__ffi_trait__vtable(self)
+				}
+			}
+		});
+		if type_id {
+			auto_impls.push(quote! {
+				unsafe impl<'__ffi_dyn, #tparams_decl> ffi_trait::Downcast for dyn #tpath #m + '__ffi_dyn where #is_ffi #is_ffi_m {
+					fn type_id(vt: &#vtable #targs) -> ::core::option::Option<ffi_trait::FFITypeId> {
+						<dyn #tpath + '__ffi_dyn as ffi_trait::Downcast>::type_id(vt)
+					}
+				}
+			});
+		}
+		if consts_bound.is_none() {
+			let from_body = into_std(m);
+			auto_impls.push(quote! {
+				ffi_trait::__ffi_trait__if_boxed! {
+					impl<'__ffi_dyn, #tparams_decl> ::core::convert::From<ffi_trait::FFIDynBox<dyn #tpath #m + '__ffi_dyn>> for ffi_trait::__Box<dyn #tpath #m + '__ffi_dyn>
+					where #is_ffi #is_ffi_m ffi_trait::FFIDynBox<dyn #tpath #m + '__ffi_dyn>: #tpath {
+						fn from(x: ffi_trait::FFIDynBox<dyn #tpath #m + '__ffi_dyn>) -> Self {
+							#from_body
+						}
+					}
+				}
+			});
+		}
+		// the bounds can be dropped when upcasting, the 1st bit of `i` being `Send` and the 2nd `Sync`
+		for (j, to) in combos.iter().enumerate().filter(|(j, _)| j & i == *j) {
+			if j != i {
+				let is_ffi_to = if generic && j != 0 {
+					quote!(for<'__ffi_any> dyn #tpath #to + '__ffi_any: ffi_trait::FFITrait<Vtable = #vtable #targs>,)
+				} else { quote!() };
+				auto_impls.push(quote! {
+					#[diagnostic::do_not_recommend]
+					unsafe impl<'__ffi_dyn, #tparams_decl> ffi_trait::Upcast<dyn #tpath #to + '__ffi_dyn> for dyn #tpath #m + '__ffi_dyn where #is_ffi #is_ffi_m #is_ffi_to {
+						unsafe fn upcast_vtable(vt: ::core::ptr::NonNull<#vtable #targs>) -> ::core::ptr::NonNull<#vtable #targs> { vt }
+					}
+				});
+			}
+			auto_impls.push(quote! {
+				#(
+					#[diagnostic::do_not_recommend]
+					unsafe impl<'__ffi_dyn> ffi_trait::Upcast<dyn #supers #to + '__ffi_dyn> for dyn #name #m + '__ffi_dyn {
+						unsafe fn upcast_vtable(vt: ::core::ptr::NonNull<#vtable>) -> ::core::ptr::NonNull<<dyn #supers #to + '__ffi_dyn as ffi_trait::FFITrait>::Vtable> {
+							::core::ptr::NonNull::new_unchecked(::core::ptr::addr_of_mut!((*vt.as_ptr()).#superfields))
+						}
+					}
+				)*
+			});
+		}
+	}
+
+	Ok(quote! {
 		#(#rawmethods)*
 
-		#vis trait #name {
+		// lets a `dyn Trait` find the vtable of its concrete type, since every
+		// implementor gets this through the blanket impl below
+		#[doc(hidden)]
+		#bound_note
+		#vis trait #dyn_vt <#tparams_decl> {
+			fn __ffi_trait__vtable<'__ffi_dyn>(&'__ffi_dyn self) -> &'static #vtable #targs;
+		}
+
+		impl<#tparams_decl __FFIImpl: #impl_bound #query_bound #consts_bound> #dyn_vt #targs for __FFIImpl where #is_ffi {
+			fn __ffi_trait__vtable<'__ffi_dyn>(&'__ffi_dyn self) -> &'static #vtable #targs {
+				<dyn #tpath + '__ffi_dyn as ffi_trait::VtableFor<__FFIImpl>>::VTABLE
+			}
+		}
+
+		#vis trait #name <#tparams_decl>: #dyn_vt #targs #superbound {
+			#(#assoc_decls)*
 			#(#methods)*
+			#rust_method
+			#type_id_method
+		}
+
+		#consts_def
+
+		#[repr(C)]
+		#derives
+		#[allow(non_snake_case)]
+		#vis struct #vtable <#tparams_decl> {
+			pub __ffi_trait__size: usize,
+			pub __ffi_trait__align: usize,
+			pub __ffi_trait__drop_in_place: ::core::option::Option<unsafe extern "C" fn(*mut ())>,
+			pub __ffi_trait__dealloc: ::core::option::Option<unsafe extern "C" fn(*mut ())>,
+			pub __ffi_trait__vtable_size: usize,
+			#rust_field
+			#type_id_field
+			#query_field
+			#(pub #superfields: <dyn #supers as ffi_trait::FFITrait>::Vtable,)*
+			#(pub #const_names: #const_tys,)*
+			#(pub #methodnames: #fntys,)*
+			#marker
 		}
 
+		unsafe impl<#tparams_decl> ffi_trait::GenericVtableLayout for #vtable #targs {}
+		#[diagnostic::do_not_recommend]
+		unsafe impl<#tparams_decl> ffi_trait::FfiSafe for #vtable #targs {}
+
+		/// The layout of the vtable, but every method may be null so
+		/// that foreign code can fill it in. See `ffi_trait::VtableBuilder`.
 		#[repr(C)]
-		#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-		#vis struct #vtable {
+		#derives
+		#[allow(non_snake_case)]
+		#vis struct #builder <#tparams_decl> {
 			pub __ffi_trait__size: usize,
 			pub __ffi_trait__align: usize,
 			pub __ffi_trait__drop_in_place: ::core::option::Option<unsafe extern "C" fn(*mut ())>,
 			pub __ffi_trait__dealloc: ::core::option::Option<unsafe extern "C" fn(*mut ())>,
-			#(#fields),*
+			pub __ffi_trait__vtable_size: usize,
+			#rust_field
+			#type_id_field
+			#query_field
+			#(pub #superfields: <dyn #supers as ffi_trait::VtableBuilder>::Builder,)*
+			#(pub #const_names: #const_tys,)*
+			#(pub #methodnames: #builderfntys,)*
+			#marker
 		}
 
-		unsafe impl ffi_trait::GenericVtableLayout for #vtable {}
+		#copy_impls
 
-		impl ffi_trait::FFITrait for dyn #name {
-			type Vtable = #vtable;
+		// everything is null except for the size, which is that of this version of the trait
+		impl<#tparams_decl> ::core::default::Default for #builder #targs {
+			fn default() -> Self {
+				Self {
+					__ffi_trait__size: 0,
+					__ffi_trait__align: 0,
+					__ffi_trait__drop_in_place: None,
+					__ffi_trait__dealloc: None,
+					__ffi_trait__vtable_size: ::core::mem::size_of::<#vtable #targs>(),
+					__ffi_trait__rust: None,
+					#type_id_default
+					#query_default
+					#(#superfields: ::core::default::Default::default(),)*
+					#(#const_names: #const_defaults,)*
+					#(#methodnames: None,)*
+					#marker_value
+				}
+			}
 		}
 
-		impl<T: #name> ffi_trait::IntoTraitObject<dyn #name> for T {
-			const VTABLE: &'static #vtable = &#vtable {
-				__ffi_trait__size: ::core::mem::size_of::<Self>(),
-				__ffi_trait__align: ::core::mem::align_of::<Self>(),
-				__ffi_trait__drop_in_place: if ::core::mem::needs_drop::<Self>() {
-					Some(ffi_trait::__ffi_trait__raw_drop_in_place::<Self>)
+		unsafe impl<#tparams_decl> ffi_trait::GenericVtableLayout for #builder #targs {}
+		#[diagnostic::do_not_recommend]
+		unsafe impl<#tparams_decl> ffi_trait::FfiSafe for #builder #targs {}
+
+		unsafe impl<'__ffi_dyn, #tparams_decl> ffi_trait::VtableBuilder for dyn #tpath + '__ffi_dyn where #is_ffi {
+			type Builder = #builder #targs;
+
+			// only the slots every version of the trait has are read, once the size says they're there
+			unsafe fn validate_ptr(b: ::core::ptr::NonNull<#builder #targs>) -> ::core::result::Result<(), ffi_trait::VtableError> {
+				let b = b.as_ptr();
+				ffi_trait::__ffi_trait__check_header(&*(b as *const ffi_trait::GenericVtable))?;
+				let size = ::core::ptr::addr_of!((*b).__ffi_trait__vtable_size).read();
+				if size < #required_size {
+					return ::core::result::Result::Err(ffi_trait::VtableError::TooShort(size));
+				}
+				#(<dyn #supers as ffi_trait::VtableBuilder>::validate_ptr(::core::ptr::NonNull::new_unchecked(::core::ptr::addr_of_mut!((*b).#superfields)))?;)*
+				#(
+					if ::core::ptr::addr_of!((*b).#requiredmethods).read().is_none() {
+						return ::core::result::Result::Err(ffi_trait::VtableError::NullMethod(stringify!(#requiredmethods)));
+					}
+				)*
+				::core::result::Result::Ok(())
+			}
+		}
+
+		#(
+			impl<'__ffi_dyn> ffi_trait::FFITrait for dyn #name #inst_targs + '__ffi_dyn {
+				type Vtable = #vtable #inst_targs;
+				#query_fn
+			}
+
+			unsafe impl<'__ffi_dyn> ffi_trait::Interface for dyn #name #inst_targs + '__ffi_dyn {
+				const ID: ffi_trait::FFITypeId = ffi_trait::FFITypeId { high: #id_high, low: #id_low };
+			}
+
+			impl<'__ffi_dyn> ffi_trait::CHeader for dyn #name #inst_targs + '__ffi_dyn {
+				const C_HEADER: &'static str = #inst_headers;
+			}
+		)*
+
+		#(#inst_checks)*
+
+		// `dyn Trait + 'a` may only hold data which lives for `'a`, which is
+		// also what makes downcasting a `dyn Trait + 'static` sound
+		unsafe impl<'__ffi_dyn, #tparams_decl __FFIImpl: #impl_bound #query_bound #consts_bound + '__ffi_dyn> ffi_trait::VtableFor<__FFIImpl> for dyn #tpath + '__ffi_dyn where #is_ffi {
+			const VTABLE: &'static #vtable #targs = &#vtable {
+				__ffi_trait__size: ::core::mem::size_of::<__FFIImpl>(),
+				__ffi_trait__align: ::core::mem::align_of::<__FFIImpl>(),
+				__ffi_trait__drop_in_place: if ::core::mem::needs_drop::<__FFIImpl>() {
+					Some(ffi_trait::__ffi_trait__raw_drop_in_place::<__FFIImpl>)
 				} else { None },
-				__ffi_trait__dealloc: None,
-				#(#methodnames: #rawnames::<Self>),*
+				__ffi_trait__dealloc: ffi_trait::__ffi_trait__dealloc_fn::<__FFIImpl>(),
+				__ffi_trait__vtable_size: ::core::mem::size_of::<#vtable #targs>(),
+				__ffi_trait__rust: Some(&ffi_trait::__ffi_trait__RustVtable {
+					binary: &ffi_trait::__ffi_trait__BINARY,
+					into_std: unsafe {
+						::core::mem::transmute::<
+							unsafe fn(::core::ptr::NonNull<()>) -> *mut (dyn #tpath + '__ffi_dyn),
+							unsafe fn(::core::ptr::NonNull<()>) -> *mut (dyn #tpath + 'static)
+						>(<__FFIImpl as #tpath>::__ffi_trait__into_std::<'__ffi_dyn>)
+					}
+				}),
+				#type_id_value
+				#query_value
+				#(#superfields: *<dyn #supers + '__ffi_dyn as ffi_trait::VtableFor<__FFIImpl>>::VTABLE,)*
+				#(#const_names: <__FFIImpl as #consts_trait #targs>::#const_names,)*
+				#(#methodnames: #rawvalues,)*
+				#marker_value
 			};
 		}
 
-		static #dyn_vt: #vtable = #vtable {
-			__ffi_trait__size: ::core::mem::size_of_val(self),
-			__ffi_trait__align: ::core::mem::align_of_val(self),
-			__ffi_trait__drop_in_place: ffi_trait::__ffi_trait__raw_dyn_drop_in_place::<Self>,
-			__ffi_trait__dealloc: None,
-			#(#methodnames: #rawnames::<Self>),*
-		};
+		// the upcasts aren't recommended, so that a missing ancestor isn't followed by a list of them all
+		#(
+			#[diagnostic::do_not_recommend]
+			unsafe impl<'__ffi_dyn> ffi_trait::Upcast<dyn #supers + '__ffi_dyn> for dyn #name + '__ffi_dyn {
+				unsafe fn upcast_vtable(vt: ::core::ptr::NonNull<#vtable>) -> ::core::ptr::NonNull<<dyn #supers + '__ffi_dyn as ffi_trait::FFITrait>::Vtable> {
+					::core::ptr::NonNull::new_unchecked(::core::ptr::addr_of_mut!((*vt.as_ptr()).#superfields))
+				}
+			}
+		)*
+
+		#[diagnostic::do_not_recommend]
+		impl<#tparams_decl __FFISub: ?Sized #(+ ffi_trait::Upcast<dyn #supers>)*> ffi_trait::__ffi_trait__Ancestors<__FFISub> for dyn #tpath where #is_ffi {}
+
+		impl<#tparams_decl> ffi_trait::__ffi_trait__Since for dyn #tpath where #is_ffi {
+			const SINCE: bool = #has_since;
+		}
+
+		#ancestor_checks
+
+		#downcast
 
-		impl ffi_trait::IntoTraitObjectRuntime<dyn #name> dyn #name {
-			fn get_vt<'a>(&'a self) -> &'a #vtable {
-				&#dyn_vt
+		// not recommended, so that an implementor missing `Send` isn't told about the trait objects
+		#[diagnostic::do_not_recommend]
+		impl<'__ffi_dyn, #tparams_decl> ffi_trait::IntoTraitObjectRuntime<dyn #tpath + '__ffi_dyn> for dyn #tpath + '__ffi_dyn where #is_ffi {
+			fn get_vt(&self) -> &#vtable #targs {
+				<Self as #dyn_vt #targs>::__ffi_trait__vtable(self)
 			}
 		}
 
-		impl<'a> #name for ffi_trait::FFIDynRef<'a, dyn #name> {
-			#(#methodimplsi)*
+		#refimpl
+		#mutimpl
+
+		ffi_trait::__ffi_trait__if_boxed! {
+			#boximpl
+
+			#into_box
 		}
 
-		impl<'a> #name for ffi_trait::FFIDynMut<'a, dyn #name> {
-			#(#methodimpls)*
+		#(#auto_impls)*
+	})
+}
+
+/// Implements `StableTypeId`, with an id made from the crate's name and version, the
+/// path of the type and the ids of its type parameters.
+#[proc_macro_derive(StableTypeId)]
+pub fn derive_stable_type_id(item: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(item as DeriveInput);
+	expand_stable_type_id(input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+fn expand_stable_type_id(input: DeriveInput) -> Result<proc_macro2::TokenStream> {
+	if let Some(x) = input.generics.params.iter().find(|x| !matches!(x, GenericParam::Type(_))) {
+		return Err(Error::new_spanned(x, "`StableTypeId` can't be derived for types with lifetime or const parameters"));
+	}
+
+	let name = &input.ident;
+	let mut generics = input.generics.clone();
+	for param in generics.type_params_mut() {
+		param.bounds.push(parse_quote!(ffi_trait::StableTypeId));
+	}
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+	let params = generics.type_params().map(|x| &x.ident);
+
+	// the defining crate's version keeps apart the types of different versions. Nothing
+	// depending on where the crate was built goes in, so that a host and its plugins agree
+	Ok(quote! {
+		unsafe impl #impl_generics ffi_trait::StableTypeId for #name #ty_generics #where_clause {
+			const ID: ffi_trait::FFITypeId = ffi_trait::FFITypeId::from_name(::core::concat!(
+				::core::env!("CARGO_PKG_NAME"), " ", ::core::env!("CARGO_PKG_VERSION"), " ",
+				::core::module_path!(), "::", ::core::stringify!(#name)
+			))
+				#(.with_arg(<#params as ffi_trait::StableTypeId>::ID))*;
 		}
-	}).into();
-	x
-}
\ No newline at end of file
+	})
+}
+
+/// Implements `FfiSafe` for a `#[repr(C)]` or `#[repr(transparent)]` type, or an
+/// enum with a primitive representation, checking that all its fields are `FfiSafe`.
+#[proc_macro_derive(FfiSafe)]
+pub fn derive_ffi_safe(item: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(item as DeriveInput);
+	expand_ffi_safe(input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+fn expand_ffi_safe(input: DeriveInput) -> Result<proc_macro2::TokenStream> {
+	let reprs = input.attrs.iter().filter(|x| x.path.is_ident("repr")).flat_map(|x| match x.parse_meta() {
+		Ok(Meta::List(l)) => l.nested.into_iter().filter_map(|x| match x {
+			NestedMeta::Meta(Meta::Path(p)) => p.get_ident().map(|x| x.to_string()),
+			_ => None
+		}).collect(),
+		_ => Vec::new()
+	}).collect::<Vec<_>>();
+	let c = reprs.iter().any(|x| x == "C" || x == "transparent");
+	let int = reprs.iter().any(|x| matches!(&**x, "u8" | "u16" | "u32" | "u64" | "usize" | "i8" | "i16" | "i32" | "i64" | "isize"));
+
+	let fields = match &input.data {
+		Data::Struct(x) => x.fields.iter().map(|x| &x.ty).collect::<Vec<_>>(),
+		Data::Enum(x) => x.variants.iter().flat_map(|x| x.fields.iter().map(|x| &x.ty)).collect(),
+		Data::Union(x) => x.fields.named.iter().map(|x| &x.ty).collect()
+	};
+	match &input.data {
+		Data::Enum(_) => if !c && !int {
+			return Err(Error::new_spanned(&input.ident, "`FfiSafe` can only be derived for enums with a `#[repr(C)]` or primitive representation, add e.g. `#[repr(u8)]`"));
+		},
+		_ => if !c {
+			return Err(Error::new_spanned(&input.ident, "`FfiSafe` can only be derived for `#[repr(C)]` or `#[repr(transparent)]` types, add `#[repr(C)]`"));
+		}
+	}
+
+	let name = &input.ident;
+	let mut generics = input.generics.clone();
+	for param in generics.type_params_mut() {
+		param.bounds.push(parse_quote!(ffi_trait::FfiSafe));
+	}
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+	let checks = fields.iter().map(|ty| quote_spanned! {ty.span()=>
+		ffi_trait::__ffi_trait__assert_ffi_safe::<#ty>();
+	});
+
+	Ok(quote! {
+		unsafe impl #impl_generics ffi_trait::FfiSafe for #name #ty_generics #where_clause {}
+
+		const _: () = {
+			#[allow(dead_code)]
+			fn check #impl_generics () #where_clause {
+				#(#checks)*
+			}
+		};
+	})
+}
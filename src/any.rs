@@ -0,0 +1,186 @@
+use crate::{FFITrait, FFIDynRef, FFIDynMut};
+
+/// An identifier for a type which, unlike `core::any::TypeId`, is the same
+/// in every binary, so that objects which crossed a dylib boundary can be
+/// downcast. Types get theirs from `StableTypeId`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FFITypeId {
+	pub high: u64,
+	pub low: u64
+}
+
+const FNV_OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+const fn fnv(mut hash: u128, bytes: &[u8]) -> u128 {
+	let mut i = 0;
+	while i < bytes.len() {
+		hash ^= bytes[i] as u128;
+		hash = hash.wrapping_mul(FNV_PRIME);
+		i += 1;
+	}
+	hash
+}
+
+impl FFITypeId {
+	/// A 128-bit FNV-1a hash of `name`, which must only ever name one type.
+	pub const fn from_name(name: &str) -> Self {
+		Self::from_hash(fnv(FNV_OFFSET, name.as_bytes()))
+	}
+
+	/// Mixes `bytes` into the id, e.g. the layout of the type.
+	pub const fn with_bytes(self, bytes: &[u8]) -> Self {
+		Self::from_hash(fnv(((self.high as u128) << 64) | self.low as u128, bytes))
+	}
+
+	/// The id of an instantiation of the generic type whose id is `self`, with a
+	/// type argument whose id is `arg`. It's applied for each argument in order.
+	pub const fn with_arg(self, arg: FFITypeId) -> Self {
+		self.with_bytes(&arg.high.to_le_bytes()).with_bytes(&arg.low.to_le_bytes())
+	}
+
+	pub fn of<T: StableTypeId + ?Sized>() -> Self {
+		T::ID
+	}
+
+	// used by the proc macro for the types which didn't opt in to a type id,
+	// whose type id slot returns zeros
+	#[doc(hidden)]
+	#[allow(non_upper_case_globals)]
+	pub const __ffi_trait__NONE: Self = Self { high: 0, low: 0 };
+
+	const fn from_hash(hash: u128) -> Self {
+		Self { high: (hash >> 64) as u64, low: hash as u64 }
+	}
+}
+
+/// A type with an `FFITypeId`, which objects can be downcast to.
+///
+/// `#[derive(StableTypeId)]` makes the id from the name and version of the
+/// crate, the path of the type and the ids of its type parameters, so that it
+/// stays the same across binaries built from the same source, wherever they
+/// were built. Types defined in function bodies share the path of the module,
+/// so two of them with the same name mustn't both derive it.
+///
+/// # Safety
+/// No other type may have the same `ID`, since downcasting trusts it.
+#[diagnostic::on_unimplemented(
+	message = "`{Self}` has no stable type id",
+	note = "add `#[derive(StableTypeId)]` to it"
+)]
+pub unsafe trait StableTypeId: 'static {
+	const ID: FFITypeId;
+}
+
+// not recommended, since a type without an id wants a derive rather than a list of these
+macro_rules! stable_type_id {
+	($($t:ty),*) => { $(#[diagnostic::do_not_recommend] unsafe impl StableTypeId for $t {
+		const ID: FFITypeId = FFITypeId::from_name(concat!("ffi_trait::", stringify!($t)));
+	})* };
+}
+
+stable_type_id!((), bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+/// Implemented on the objects of `#[ffi_trait(type_id)]` traits, whose
+/// vtables have a slot returning the `FFITypeId` of the concrete type. It
+/// returns zeros for the types whose impl of the trait didn't opt in with
+/// `#[ffi_trait(type_id)]`, and foreign code may leave it null, in which
+/// cases downcasting fails.
+///
+/// # Safety
+/// `type_id` must only return the id of the concrete type of the data.
+pub unsafe trait Downcast: FFITrait {
+	fn type_id(vt: &Self::Vtable) -> Option<FFITypeId>;
+}
+
+impl<'a, T: Downcast + ?Sized + 'static> FFIDynRef<'a, T> {
+	pub fn type_id(&self) -> Option<FFITypeId> {
+		T::type_id(self.vtable())
+	}
+
+	/// Whether the concrete type is `U`.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `<dyn Any>::is`
+	pub fn is<U: StableTypeId>(&self) -> bool {
+		self.type_id() == Some(U::ID)
+	}
+
+	/// Gets a reference to the data if its concrete type is `U`.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `<dyn Any>::downcast_ref`
+	pub fn downcast_ref<U: StableTypeId>(self) -> Option<&'a U> {
+		if self.is::<U>() {
+			Some(unsafe { &*(self.data().as_ptr() as *const U) })
+		} else {
+			None
+		}
+	}
+}
+
+impl<'a, T: Downcast + ?Sized + 'static> FFIDynMut<'a, T> {
+	pub fn type_id(&self) -> Option<FFITypeId> {
+		T::type_id(self.vtable())
+	}
+
+	/// Whether the concrete type is `U`.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `<dyn Any>::is`
+	pub fn is<U: StableTypeId>(&self) -> bool {
+		self.type_id() == Some(U::ID)
+	}
+
+	/// Gets a reference to the data if its concrete type is `U`.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `<dyn Any>::downcast_ref`
+	pub fn downcast_ref<U: StableTypeId>(&self) -> Option<&U> {
+		if self.is::<U>() {
+			Some(unsafe { &*(self.data().as_ptr() as *const U) })
+		} else {
+			None
+		}
+	}
+
+	/// Gets a mutable reference to the data if its concrete type is `U`.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `<dyn Any>::downcast_mut`
+	pub fn downcast_mut<U: StableTypeId>(&mut self) -> Option<&mut U> {
+		if self.is::<U>() {
+			Some(unsafe { &mut *(self.data().as_ptr() as *mut U) })
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(feature = "boxed")]
+impl<T: Downcast + ?Sized + 'static> crate::FFIDynBox<T> {
+	/// Gets back the `Box` if the concrete type is `U`, giving the box
+	/// back otherwise. The allocation is reused if this crate's allocator
+	/// made it, and the data is moved to a new one otherwise, e.g. for boxes
+	/// made by another binary or by C.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `Box<dyn Any>::downcast`
+	pub fn downcast<U: StableTypeId>(b: Self) -> Result<alloc::boxed::Box<U>, Self> {
+		if !b.as_ref().is::<U>() {
+			return Err(b);
+		}
+		let x = Self::into_nonnull(b);
+		let data = x.data.as_ptr() as *mut U;
+		unsafe {
+			let dealloc = crate::generic(x.vtable.as_ref()).dealloc;
+			if dealloc.is_some_and(|f| core::ptr::fn_addr_eq(f, crate::__ffi_trait__raw_dealloc::<U> as unsafe extern "C" fn(*mut ()))) {
+				return Ok(alloc::boxed::Box::from_raw(data));
+			}
+			let b = alloc::boxed::Box::new(core::ptr::read(data));
+			crate::dealloc_nonnull(x);
+			Ok(b)
+		}
+	}
+}
@@ -1,42 +1,78 @@
-extern crate alloc;
-
 use alloc::boxed::Box;
 
-use core::ops::{Deref, DerefMut};
+use core::ops::Deref;
 use core::mem::ManuallyDrop;
 use core::marker::PhantomData;
+use core::ptr::NonNull;
 
-use crate::{FFITrait, FFIDynNonNull, FFIDynPtr, FFIDynRef, FFIDynMut, IntoTraitObjectRuntime};
+use crate::{FFITrait, FFIDynNonNull, FFIDynPtr, FFIDynRef, FFIDynMut, IntoTraitObjectRuntime, Upcast, VtableBuilder, VtableError};
 
 /// An FFI-safe equivalent of `Box<dyn T>`
+///
+/// Dropping the box runs the vtable's `drop_in_place` followed by its
+/// `dealloc`, so the data must have been allocated in a way that the
+/// vtable's `dealloc` can free.
 #[repr(transparent)]
 pub struct FFIDynBox<T: FFITrait + ?Sized>(FFIDynNonNull<T>, PhantomData<T>);
 
-impl<T: FFITrait + IntoTraitObjectRuntime<T> + ?Sized> From<Box<T>> for FFIDynBox<T> {
-	fn from(x: Box<T>) -> Self {
+unsafe impl<T: FFITrait + Send + ?Sized> Send for FFIDynBox<T> {}
+unsafe impl<T: FFITrait + Sync + ?Sized> Sync for FFIDynBox<T> {}
+
+impl<T: FFITrait + ?Sized, U: IntoTraitObjectRuntime<T> + ?Sized> From<Box<U>> for FFIDynBox<T> {
+	fn from(x: Box<U>) -> Self {
 		FFIDynBox::from_std(x)
 	}
 }
 
-impl<'a, T: FFITrait + ?Sized> Deref for FFIDynBox<T> {
-	type Target = FFIDynRef<'a, T>;
-	fn deref(&self) -> &FFIDynRef<'a, T> {
-		
+/// Only `Deref` is provided: handing out a `&mut FFIDynMut<'static, T>` would
+/// allow the box's contents to be swapped out from under it.
+impl<T: FFITrait + ?Sized> Deref for FFIDynBox<T> {
+	type Target = FFIDynMut<'static, T>;
+	fn deref(&self) -> &FFIDynMut<'static, T> {
+		// SAFETY: FFIDynMut is a transparent wrapper around FFIDynNonNull,
+		// and the returned reference can't outlive the box
+		unsafe { &*(&self.0 as *const FFIDynNonNull<T> as *const FFIDynMut<'static, T>) }
 	}
+}
 
+impl<T: FFITrait + ?Sized> Drop for FFIDynBox<T> {
+	fn drop(&mut self) {
+		unsafe {
+			crate::drop_in_place_nonnull(self.0);
+			crate::dealloc_nonnull(self.0);
+		}
+	}
 }
 
 impl<T: FFITrait + ?Sized> FFIDynBox<T> {
-	pub fn from_std(x: Box<T>) -> Self where T: IntoTraitObjectRuntime<T> {
+	/// Moves `x` onto the heap and wraps it.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `Box::new(x) as Box<dyn T>`
+	pub fn new<U: IntoTraitObjectRuntime<T>>(x: U) -> Self {
+		Self::from_std(Box::new(x))
+	}
+
+	pub fn from_std<U: IntoTraitObjectRuntime<T> + ?Sized>(x: Box<U>) -> Self {
 		unsafe {
 			Self::from_raw_std(Box::into_raw(x))
 		}
 	}
 
-	pub unsafe fn from_raw_std(x: *mut T) -> Self where T: IntoTraitObjectRuntime<T> {
-		Self::from_raw(FFIDynMut::from_std(&mut *x).to_ptr())
+	/// Creates an `FFIDynBox` from a pointer returned by `Box::into_raw`.
+	///
+	/// # Safety
+	/// `x` must come from `Box::into_raw`, and the vtable returned by
+	/// `IntoTraitObjectRuntime::get_vt` must free it correctly.
+	pub unsafe fn from_raw_std<U: IntoTraitObjectRuntime<T> + ?Sized>(x: *mut U) -> Self {
+		Self::from_raw((&mut *x).dyn_mut().to_ptr())
 	}
 
+	/// Creates an `FFIDynBox` from an `FFIDynPtr`.
+	///
+	/// # Safety
+	/// The pointer must be non-null, and the data must be owned by the
+	/// returned box and freeable by the vtable's `dealloc`.
 	pub unsafe fn from_raw(x: FFIDynPtr<T>) -> Self {
 		Self::from_nonnull(FFIDynNonNull::new_unchecked(x))
 	}
@@ -45,6 +81,21 @@ impl<T: FFITrait + ?Sized> FFIDynBox<T> {
 		Self(x, PhantomData)
 	}
 
+	/// Creates an `FFIDynBox` from data and a vtable filled in by foreign
+	/// code, checking that none of the vtable's methods are null. The vtable
+	/// may be one made for an older version of the trait, which is shorter.
+	///
+	/// # Safety
+	/// The box takes ownership of `data`: it must be valid until the box
+	/// is dropped, at which point the vtable's `drop_in_place` and `dealloc`
+	/// must release it. Calling any of the vtable's functions with it must
+	/// be sound, following the signature and contract of the corresponding
+	/// trait method. `vtable` must be valid for as long as the box and for
+	/// reads of the `vtable_size` it gives, see `VtableBuilder::validate_ptr`.
+	pub unsafe fn from_foreign(data: NonNull<()>, vtable: NonNull<T::Builder>) -> Result<Self, VtableError> where T: VtableBuilder {
+		Ok(Self::from_nonnull(FFIDynNonNull::from_raw_parts(data, T::check_ptr(vtable)?)))
+	}
+
 	pub fn leak<'a>(b: Self) -> FFIDynMut<'a, T> where T: 'a {
 		unsafe {
 			Self::into_raw(b).to_ref_mut()
@@ -59,4 +110,36 @@ impl<T: FFITrait + ?Sized> FFIDynBox<T> {
 		ManuallyDrop::new(b).0
 	}
 
-}
\ No newline at end of file
+	/// Borrows the contents of the box.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `&*b`
+	pub fn as_ref(&self) -> FFIDynRef<'_, T> {
+		unsafe { self.0.to_ref() }
+	}
+
+	/// Mutably borrows the contents of the box.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `&mut *b`
+	pub fn as_mut(&mut self) -> FFIDynMut<'_, T> {
+		unsafe { self.0.to_ref_mut() }
+	}
+
+	pub fn data(&self) -> NonNull<()> { self.0.data }
+
+	pub fn vtable(&self) -> &T::Vtable { unsafe { self.0.vtable.as_ref() } }
+
+	/// The vtable, which may be shorter than `T::Vtable` if it came from `from_foreign`.
+	pub fn vtable_ptr(&self) -> NonNull<T::Vtable> { self.0.vtable }
+
+	/// Converts this into a box of a supertrait object. The supertrait's
+	/// vtable drops and deallocates the data the same way.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `Box<dyn T> as Box<dyn U>`
+	pub fn upcast<U: FFITrait + ?Sized>(b: Self) -> FFIDynBox<U> where T: Upcast<U> {
+		let vtable = unsafe { T::upcast_vtable(b.vtable_ptr()) };
+		unsafe { FFIDynBox::from_nonnull(FFIDynNonNull::from_raw_parts(Self::into_nonnull(b).data, vtable)) }
+	}
+}
@@ -0,0 +1,209 @@
+#[cfg(feature = "boxed")] use alloc::boxed::Box;
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::AtomicU32;
+#[cfg(feature = "boxed")] use core::sync::atomic::Ordering;
+
+/// A COM interface identifier
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct Guid {
+	pub data1: u32,
+	pub data2: u16,
+	pub data3: u16,
+	pub data4: [u8; 8]
+}
+
+impl Guid {
+	/// The id of `IUnknown`, `00000000-0000-0000-C000-000000000046`
+	pub const IUNKNOWN: Guid = Guid { data1: 0, data2: 0, data3: 0, data4: [0xC0, 0, 0, 0, 0, 0, 0, 0x46] };
+}
+
+impl fmt::Debug for Guid {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-", self.data1, self.data2, self.data3, self.data4[0], self.data4[1])?;
+		for x in &self.data4[2..] {
+			write!(f, "{:02X}", x)?;
+		}
+		Ok(())
+	}
+}
+
+/// A COM status code
+pub type HResult = i32;
+
+pub const S_OK: HResult = 0;
+pub const E_NOINTERFACE: HResult = 0x80004002_u32 as i32;
+pub const E_POINTER: HResult = 0x80004003_u32 as i32;
+
+/// The functions every COM vtable begins with
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct IUnknownVtable {
+	pub query_interface: unsafe extern "system" fn(NonNull<()>, *const Guid, *mut *mut ()) -> HResult,
+	pub add_ref: unsafe extern "system" fn(NonNull<()>) -> u32,
+	pub release: unsafe extern "system" fn(NonNull<()>) -> u32
+}
+
+#[cfg(feature = "boxed")]
+impl IUnknownVtable {
+	/// The functions of an object made by `ComPtr::new`
+	pub const fn new<I: ComInterface + ?Sized, T>() -> Self {
+		Self {
+			query_interface: com_query_interface::<I, T>,
+			add_ref: com_add_ref::<T>,
+			release: com_release::<T>
+		}
+	}
+}
+
+/// This marker specifies that the type begins with an `IUnknownVtable`
+pub unsafe trait ComVtableLayout {}
+
+/// Implemented on the objects of `#[ffi_trait(layout = "com")]` traits, whose
+/// vtables begin with `QueryInterface`, `AddRef` and `Release` instead of a
+/// `GenericVtable`.
+///
+/// # Safety
+/// `IID` must identify this interface and no other.
+pub unsafe trait ComInterface {
+	type Vtable: ComVtableLayout + 'static;
+	const IID: Guid;
+}
+
+/// Implemented on a COM interface for every type which implements it,
+/// with the `boxed` feature.
+///
+/// # Safety
+/// `VTABLE` must be a valid vtable for objects made by `ComPtr::new` holding a `T`.
+pub unsafe trait ComVtableFor<T>: ComInterface {
+	const VTABLE: &'static Self::Vtable;
+}
+
+/// A reference-counted pointer to a COM object, which is a pointer to a struct
+/// whose first field is the vtable pointer. Cloning calls `AddRef` and
+/// dropping calls `Release`.
+///
+/// # Standard Equivalent
+/// This type is equivalent to `Arc<dyn T>`, or a smart pointer like `CComPtr`
+#[repr(transparent)]
+pub struct ComPtr<I: ComInterface + ?Sized>(NonNull<*const I::Vtable>, PhantomData<I>);
+
+/// How objects made by `ComPtr::new` are laid out
+#[repr(C)]
+struct ComObject<T> {
+	vtable: *const (),
+	refs: AtomicU32,
+	value: T
+}
+
+impl<I: ComInterface + ?Sized> ComPtr<I> {
+	/// Moves `x` into a new COM object.
+	#[cfg(feature = "boxed")]
+	pub fn new<T>(x: T) -> Self where I: ComVtableFor<T> {
+		let obj = Box::new(ComObject {
+			vtable: I::VTABLE as *const I::Vtable as *const (),
+			refs: AtomicU32::new(1),
+			value: x
+		});
+		unsafe { Self::from_raw(NonNull::new_unchecked(Box::into_raw(obj) as *mut ())) }
+	}
+
+	/// Takes over a reference to a COM object.
+	///
+	/// # Safety
+	/// `x` must point to a live COM object implementing `I`, and the caller
+	/// must own one of its references.
+	pub unsafe fn from_raw(x: NonNull<()>) -> Self {
+		Self(x.cast(), PhantomData)
+	}
+
+	/// Gives up the pointer without releasing its reference.
+	pub fn into_raw(x: Self) -> NonNull<()> {
+		let p = x.0.cast();
+		core::mem::forget(x);
+		p
+	}
+
+	pub fn as_raw(&self) -> NonNull<()> { self.0.cast() }
+
+	pub fn vtable(&self) -> &I::Vtable {
+		unsafe { &**self.0.as_ptr() }
+	}
+
+	fn unknown(&self) -> &IUnknownVtable {
+		// SAFETY: `ComVtableLayout` guarantees the vtable begins with this
+		unsafe { &*(*self.0.as_ptr() as *const IUnknownVtable) }
+	}
+
+	/// Asks the object whether it also implements `U`.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `IUnknown::QueryInterface`
+	pub fn query<U: ComInterface + ?Sized>(&self) -> Option<ComPtr<U>> {
+		let mut out = ptr::null_mut();
+		let hr = unsafe { (self.unknown().query_interface)(self.as_raw(), &U::IID, &mut out) };
+		if hr == S_OK {
+			Some(unsafe { ComPtr::from_raw(NonNull::new(out)?) })
+		} else {
+			None
+		}
+	}
+}
+
+impl<I: ComInterface + ?Sized> Clone for ComPtr<I> {
+	fn clone(&self) -> Self {
+		unsafe {
+			(self.unknown().add_ref)(self.as_raw());
+		}
+		Self(self.0, PhantomData)
+	}
+}
+
+impl<I: ComInterface + ?Sized> Drop for ComPtr<I> {
+	fn drop(&mut self) {
+		unsafe {
+			(self.unknown().release)(self.as_raw());
+		}
+	}
+}
+
+#[cfg(feature = "boxed")]
+unsafe extern "system" fn com_query_interface<I: ComInterface + ?Sized, T>(this: NonNull<()>, iid: *const Guid, out: *mut *mut ()) -> HResult {
+	if out.is_null() {
+		return E_POINTER;
+	}
+	if iid.is_null() || (*iid != Guid::IUNKNOWN && *iid != I::IID) {
+		*out = ptr::null_mut();
+		return E_NOINTERFACE;
+	}
+	com_add_ref::<T>(this);
+	*out = this.as_ptr();
+	S_OK
+}
+
+#[cfg(feature = "boxed")]
+unsafe extern "system" fn com_add_ref<T>(this: NonNull<()>) -> u32 {
+	let obj = &*(this.as_ptr() as *const ComObject<T>);
+	obj.refs.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+#[cfg(feature = "boxed")]
+unsafe extern "system" fn com_release<T>(this: NonNull<()>) -> u32 {
+	let obj = this.as_ptr() as *mut ComObject<T>;
+	let refs = (*obj).refs.fetch_sub(1, Ordering::Release) - 1;
+	if refs == 0 {
+		core::sync::atomic::fence(Ordering::Acquire);
+		drop(Box::from_raw(obj));
+	}
+	refs
+}
+
+// used by the proc macro to find the value in an object made by `ComPtr::new`
+#[doc(hidden)]
+#[allow(non_snake_case)]
+pub unsafe fn __ffi_trait__com_value<T>(this: NonNull<()>) -> NonNull<()> {
+	NonNull::new_unchecked(ptr::addr_of_mut!((*(this.as_ptr() as *mut ComObject<T>)).value) as *mut ())
+}
@@ -1,5 +1,5 @@
-#![feature(raw)]
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+#![allow(clippy::missing_safety_doc)]
 
 //! Alternative to the `#[stable_vtable]` attribute, which is far from being
 //! implemented into the language.
@@ -7,19 +7,42 @@
 //! The `#[ffi_trait]` allows you to make traits which are FFI-safe with a
 //! defined vtable and `&[mut] dyn Trait` and `Box<dyn Trait>` equivalents.
 
+#[cfg(feature = "boxed")] extern crate alloc;
+
+use core::fmt;
 use core::mem;
 use core::ptr::NonNull;
-use core::raw::TraitObject;
+#[cfg(feature = "boxed")] use core::alloc::Layout;
 
 pub use ffi_trait_macro::*;
 
 pub mod refs;
 pub mod ptr;
-//#[cfg(feature = "boxed")] pub mod boxed;
+pub mod unwind;
+pub mod safe;
+pub mod any;
+pub mod query;
+pub mod com;
+pub mod thin;
+pub mod slice;
+pub mod option;
+#[cfg(feature = "boxed")] pub mod boxed;
+#[cfg(feature = "boxed")] pub mod rc;
+#[cfg(feature = "boxed")] pub mod vec;
 
 pub use refs::*;
 pub use ptr::*;
-//#[cfg(feature = "boxed")] pub use boxed::*;
+pub use unwind::*;
+pub use safe::*;
+pub use any::*;
+pub use query::*;
+pub use com::*;
+pub use thin::*;
+pub use slice::*;
+pub use option::*;
+#[cfg(feature = "boxed")] pub use boxed::*;
+#[cfg(feature = "boxed")] pub use rc::*;
+#[cfg(feature = "boxed")] pub use vec::*;
 
 /// The layout of a generic vtable. All other vtables begin with
 /// this layout. This property is guaranteed by the `GenericVtableLayout`
@@ -36,6 +59,10 @@ pub struct GenericVtable {
 	pub drop_in_place: Option<unsafe extern "C" fn(*mut ())>,
 	/// An optional function which deallocates the data, for use in e.x. `Box`.
 	/// This allows other languages to deallocate the data.
+	///
+	/// Vtables generated by `#[ffi_trait]` free memory which was allocated
+	/// by the global allocator with the layout of the concrete type, so
+	/// this is `None` without the `boxed` feature.
 	pub dealloc: Option<unsafe extern "C" fn(*mut ())>
 }
 
@@ -72,20 +99,27 @@ pub fn generic<T: GenericVtableLayout>(v: &T) -> &GenericVtable {
 /// problematic, we instead use `dyn` to turn the trait into
 /// an impl-able object.
 pub trait FFITrait {
-	type Vtable: GenericVtableLayout + Copy + Clone;
+	type Vtable: GenericVtableLayout + Copy + Clone + 'static;
+
+	/// The `query_interface` slot of the vtable, which only the vtables of
+	/// `#[ffi_trait(query_interface)]` traits have.
+	fn query_interface_fn(vt: &Self::Vtable) -> Option<QueryInterfaceFn> {
+		let _ = vt;
+		None
+	}
 }
 
 /// A trait for any type which can be turned into an FFI-compatible trait object
 /// at **runtime**, unlike its subtrait `IntoTraitObject` which uses a constant
 /// and isn't object safe.
 pub trait IntoTraitObjectRuntime<T: FFITrait + ?Sized> {
-	fn get_vt<'a>(&'a self) -> &'a T::Vtable;
+	fn get_vt(&self) -> &T::Vtable;
 
 	fn dyn_ref<'a>(&'a self) -> FFIDynRef<'a, T> {
 		unsafe {
 			FFIDynRef::from_raw_parts(
 				NonNull::new_unchecked(self as *const Self as *const () as *mut ()),
-				NonNull::new_unchecked(self.get_vt() as *const _ as *mut _)
+				NonNull::new_unchecked(IntoTraitObjectRuntime::get_vt(self) as *const _ as *mut _)
 			)
 		}
 	}
@@ -94,7 +128,7 @@ pub trait IntoTraitObjectRuntime<T: FFITrait + ?Sized> {
 		unsafe {
 			FFIDynMut::from_raw_parts(
 				NonNull::new_unchecked(self as *mut Self as *mut ()),
-				NonNull::new_unchecked(self.get_vt() as *const _ as *mut _)
+				NonNull::new_unchecked(IntoTraitObjectRuntime::get_vt(self) as *const _ as *mut _)
 			)
 		}
 	}
@@ -105,7 +139,166 @@ pub trait IntoTraitObjectRuntime<T: FFITrait + ?Sized> {
 /// The type argument should be a `dyn` trait as explained in `FFITrait`
 pub trait IntoTraitObject<T: FFITrait + ?Sized> : IntoTraitObjectRuntime<T> where T::Vtable: 'static {
 	const VTABLE: &'static T::Vtable;
-	fn get_vt(&self) -> &'static T::Vtable { &Self::VTABLE }
+	fn get_vt(&self) -> &'static T::Vtable { Self::VTABLE }
+}
+
+/// Implemented on an FFI trait (`dyn Trait`) for every type which implements
+/// the trait, providing that type's vtable.
+///
+/// This is what the `#[ffi_trait]` macro generates: the orphan rules
+/// don't allow it to implement `IntoTraitObject<dyn Trait>` for every `T: Trait`
+/// directly, so `IntoTraitObject` and `IntoTraitObjectRuntime` are instead
+/// implemented on top of this trait.
+///
+/// # Safety
+/// `VTABLE` must be a valid vtable for `U`.
+pub unsafe trait VtableFor<U>: FFITrait {
+	const VTABLE: &'static Self::Vtable;
+}
+
+/// Implemented on every `#[ffi_trait]` object, providing a C header which
+/// declares its vtable, a `{data, vtable}` pointer struct equivalent to
+/// `FFIDynPtr`, and an inline function calling each method.
+///
+/// The header for `Trait` is meant to be saved as `Trait.h`, which is the
+/// name the headers of other traits `#include` it by. `#[ffi_trait(c_header)]`
+/// additionally writes it to the `OUT_DIR` of the crate's build script.
+pub trait CHeader {
+	const C_HEADER: &'static str;
+}
+
+/// Implemented on every `#[ffi_trait]` object so that foreign code can
+/// implement the trait by filling in a vtable itself.
+///
+/// `Builder` has the same layout as the vtable, and as the vtable struct of
+/// the C header, except that every method may be null. Once `validate` has
+/// checked that none of them are, it can be used as the vtable.
+///
+/// Vtables made for an older version of the trait end before the methods
+/// added since, so those from foreign code are checked through a pointer
+/// with `validate_ptr`, which only reads what `vtable_size` says is there.
+///
+/// # Safety
+/// `Builder` must have the layout of `Vtable` with nullable methods, and
+/// `validate_ptr` must reject every builder which isn't a valid vtable.
+pub unsafe trait VtableBuilder: FFITrait {
+	type Builder: GenericVtableLayout + Copy + Default + 'static;
+
+	/// Checks that no method is null and that the alignment is valid, here
+	/// and in the vtables of the supertraits embedded in it.
+	///
+	/// # Safety
+	/// `b` must be valid for reads of the common part of the vtable and
+	/// `vtable_size`, and of `vtable_size` bytes if it's large enough.
+	unsafe fn validate_ptr(b: NonNull<Self::Builder>) -> Result<(), VtableError>;
+
+	/// Checks a whole vtable.
+	fn validate(b: &Self::Builder) -> Result<(), VtableError> {
+		// SAFETY: the reference covers the whole vtable
+		unsafe { Self::validate_ptr(NonNull::from(b)) }
+	}
+
+	/// Checks a vtable in place.
+	fn check(b: &Self::Builder) -> Result<&Self::Vtable, VtableError> {
+		Self::validate(b)?;
+		// SAFETY: the builder has the same layout and was just validated
+		Ok(unsafe { &*(b as *const Self::Builder as *const Self::Vtable) })
+	}
+
+	/// Checks a vtable in place, which may be shorter than `Vtable`.
+	///
+	/// # Safety
+	/// See `validate_ptr`.
+	unsafe fn check_ptr(b: NonNull<Self::Builder>) -> Result<NonNull<Self::Vtable>, VtableError> {
+		Self::validate_ptr(b)?;
+		Ok(b.cast())
+	}
+
+	/// Checks a vtable and copies it out.
+	fn build(b: &Self::Builder) -> Result<Self::Vtable, VtableError> {
+		Self::check(b).copied()
+	}
+}
+
+/// The reason a vtable filled in by foreign code was rejected
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VtableError {
+	/// The method with this name was null
+	NullMethod(&'static str),
+	/// The alignment wasn't a power of two
+	BadAlign(usize),
+	/// The vtable, of this size, is missing methods which every version of the trait has
+	TooShort(usize)
+}
+
+impl fmt::Display for VtableError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			VtableError::NullMethod(x) => write!(f, "vtable method `{}` is null", x),
+			VtableError::BadAlign(x) => write!(f, "vtable alignment {} isn't a power of two", x),
+			VtableError::TooShort(x) => write!(f, "vtable size {} is too small for the trait", x)
+		}
+	}
+}
+
+// used by the proc macro to check the common part of foreign vtables
+#[doc(hidden)]
+#[allow(non_snake_case)]
+pub fn __ffi_trait__check_header(x: &GenericVtable) -> Result<(), VtableError> {
+	if x.align.is_power_of_two() { Ok(()) } else { Err(VtableError::BadAlign(x.align)) }
+}
+
+/// Implemented on an FFI trait for each of its supertraits, whose vtables are
+/// embedded in its own vtable, and for itself.
+///
+/// `upcast_vtable` takes and returns pointers, since vtables made for an
+/// older version of the trait may be shorter than `Self::Vtable`.
+///
+/// # Safety
+/// `upcast_vtable` must return a valid vtable for the same data.
+#[diagnostic::on_unimplemented(
+	message = "`{Self}` can't be upcast to `{U}`",
+	note = "ffi_traits can only be upcast to the supertraits they list, so they must list the ffi_trait supertraits of those too"
+)]
+pub unsafe trait Upcast<U: FFITrait + ?Sized>: FFITrait {
+	/// # Safety
+	/// `vt` must point to a checked vtable of `Self`.
+	unsafe fn upcast_vtable(vt: NonNull<Self::Vtable>) -> NonNull<U::Vtable>;
+}
+
+unsafe impl<T: FFITrait + ?Sized> Upcast<T> for T {
+	unsafe fn upcast_vtable(vt: NonNull<T::Vtable>) -> NonNull<T::Vtable> { vt }
+}
+
+// used by the proc macro. an ffi_trait implements this for the traits which list
+// all of its supertraits, as only those can be upcast to them
+#[doc(hidden)]
+#[allow(non_camel_case_types)]
+#[diagnostic::on_unimplemented(
+	message = "`{Sub}` doesn't list all the ffi_trait supertraits of `{Self}`",
+	note = "ffi_traits can only be upcast to the supertraits they list, so they must list the ffi_trait supertraits of those too"
+)]
+pub trait __ffi_trait__Ancestors<Sub: ?Sized> {}
+
+// used by the proc macro. whether an ffi_trait has methods with `since`, which grow its
+// vtable, so that it can't be embedded in the vtables of subtraits
+#[doc(hidden)]
+#[allow(non_camel_case_types)]
+pub trait __ffi_trait__Since {
+	const SINCE: bool;
+}
+
+// used by the proc macro to check that `Sub` lists the supertraits of its supertrait `T`
+#[doc(hidden)]
+#[allow(non_snake_case)]
+pub fn __ffi_trait__assert_ancestors<Sub: ?Sized, T: __ffi_trait__Ancestors<Sub> + ?Sized>() {}
+
+impl<U, T: VtableFor<U> + ?Sized> IntoTraitObjectRuntime<T> for U {
+	fn get_vt(&self) -> &T::Vtable { T::VTABLE }
+}
+
+impl<U, T: VtableFor<U> + ?Sized> IntoTraitObject<T> for U {
+	const VTABLE: &'static T::Vtable = T::VTABLE;
 }
 
 macro_rules! vtable_fn {
@@ -139,6 +332,40 @@ vtable_fn!(dealloc (unsafe); x => {
 	if let Some(f) = f { f(x.data); }
 } => () => dealloc_ptr, dealloc_nonnull, dealloc_ref, dealloc_mut);
 
+// used by the proc macro to emit items which only exist with the `boxed` feature
+#[cfg(feature = "boxed")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ffi_trait__if_boxed {
+	($($tt:tt)*) => { $($tt)* };
+}
+
+#[cfg(not(feature = "boxed"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ffi_trait__if_boxed {
+	($($tt:tt)*) => {};
+}
+
+#[cfg(feature = "boxed")]
+#[doc(hidden)]
+pub use alloc::boxed::Box as __Box;
+
+// used by the proc macro to take ownership of the data in methods taking `self`
+#[cfg(feature = "boxed")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ffi_trait__take_box {
+	($ptr:expr, $t:ty) => { $crate::__Box::from_raw($ptr.as_ptr() as *mut $t) };
+}
+
+#[cfg(not(feature = "boxed"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ffi_trait__take_box {
+	($ptr:expr, $t:ty) => { compile_error!("methods taking `self` by value need the `boxed` feature of `ffi_trait`") };
+}
+
 // used by the proc macro
 #[doc(hidden)]
 #[allow(non_snake_case)]
@@ -146,51 +373,75 @@ pub unsafe extern "C" fn __ffi_trait__raw_drop_in_place<T>(ptr: *mut ()) {
 	core::ptr::drop_in_place(ptr as *mut T);
 }
 
-// oh lord please bring mercy
+// used by the proc macro
+#[cfg(feature = "boxed")]
+#[doc(hidden)]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn __ffi_trait__raw_dealloc<T>(ptr: *mut ()) {
+	// boxes of zero-sized types don't allocate
+	let layout = Layout::new::<T>();
+	if layout.size() != 0 {
+		alloc::alloc::dealloc(ptr as *mut u8, layout);
+	}
+}
 
-/// Please never ever call this function EVER. Please. Don't. EVER. Call this function.
-/// This function is used by the autogenerated code from `#[ffi_trait]`. It takes
-/// a data and vtable pointer and drops the trait object represented by them.
-/// Using this function is worse than transmute and worse than transmute_copy.
-///
-/// For God's sake please never approach this function in your life.
-/// Unless you want UB. Here's how to cause UB:
-/// - Pass a null data pointer
-/// - Pass a null vtable pointer
-/// - Pass a vtable pointer which doesn't point at a valid vtable made by Rust
-/// - Pass a data pointer which doesn't correspond with the vtable
-/// - Pass a type argument which implements `Sized` (yes, seriously)
-/// - Pass a type argument which isn't `dyn SomeTrait`
-/// - Pass a `dyn Trait` which isn't the actual trait represented by the data/vtable
-/// - Call this function twice with the same arguments
+// used by the proc macro to fill in the `dealloc` slot, which can only be
+// filled if we know the data was allocated with the global allocator
 #[doc(hidden)]
 #[allow(non_snake_case)]
-pub unsafe extern "C" fn __ffi_trait__raw_dyn_drop_in_place<T: ?Sized>(data: *mut (), vtable: *mut ()) {
-	// we can't use normal transmute here since we don't know that
-	// T isn't sized (since we don't have negative bounds).
-	//
-	// When Rust literally prevents you from using TRANSMUTE, you know
-	// something is WRONG.
+pub const fn __ffi_trait__dealloc_fn<T>() -> Option<unsafe extern "C" fn(*mut ())> {
+	#[cfg(feature = "boxed")] { Some(__ffi_trait__raw_dealloc::<T>) }
+	#[cfg(not(feature = "boxed"))] { None }
+}
 
-	// use *mut T instead of &mut T so we dont have mutable aliasing
-	let t: *mut T = mem::transmute_copy(&TraitObject { data, vtable });
-	core::ptr::drop_in_place(t);
+// used by the proc macro. the vtables it generates point to one of these, which
+// C leaves null, so that a box made by this binary can become the `Box<dyn Trait>`
+// it was, from the concrete type's `into_std`
+#[doc(hidden)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+pub struct __ffi_trait__RustVtable<F> {
+	pub binary: &'static u8,
+	pub into_std: F
 }
 
+// used by the proc macro. its address tells apart the vtables of this binary
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub static __ffi_trait__BINARY: u8 = 0;
+
 #[cfg(test)]
 mod tests {
+	use crate::*;
+
+	trait Empty {}
+
+	#[derive(Copy, Clone)]
+	#[repr(C)]
+	struct EmptyVtable(GenericVtable);
+
+	unsafe impl GenericVtableLayout for EmptyVtable {}
+
+	impl FFITrait for dyn Empty {
+		type Vtable = EmptyVtable;
+	}
+
 	#[test]
-	fn dyn_drop_in_place() {
-		unsafe {
-			let p = Box::into_raw(Box::new(Box::new(8))); // something which has a destructor inside the box
-			let mut x = Box::from_raw(p);
-			let d: *mut dyn std::fmt::Display = &mut x;
-			let std::raw::TraitObject { data, vtable } = std::mem::transmute(d);
-			assert_eq!(data as *const _, &x as *const _ as *const _);
-			std::mem::forget(x);
-			crate::__ffi_trait__raw_dyn_drop_in_place::<dyn std::fmt::Display>(data, vtable);
-			// from now on we can't access x at all, it's been dropped in place.
-			//std::mem::forget(x); forgetting something after it's been dropped - can't do, so we do it above
-		}
+	fn raw_drop_in_place() {
+		use std::rc::Rc;
+
+		let rc = Rc::new(());
+		let mut x = std::mem::ManuallyDrop::new(rc.clone());
+		let vtable = EmptyVtable(GenericVtable {
+			size: std::mem::size_of::<Rc<()>>(),
+			align: std::mem::align_of::<Rc<()>>(),
+			drop_in_place: Some(__ffi_trait__raw_drop_in_place::<Rc<()>>),
+			dealloc: None
+		});
+		let p = FFIDynPtr::<dyn Empty>::from_raw_parts(&mut *x as *mut Rc<()> as *mut (), NonNull::from(&vtable));
+		assert_eq!(Rc::strong_count(&rc), 2);
+		unsafe { drop_in_place_ptr(p); }
+		// from now on we can't access x at all, it's been dropped in place.
+		assert_eq!(Rc::strong_count(&rc), 1);
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,161 @@
+use core::fmt;
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ptr;
+
+/// An FFI-safe equivalent of `Option<T>`, where `value` is initialized if and
+/// only if `is_some` is `true`.
+#[repr(C)]
+pub struct FFIOption<T> {
+	is_some: bool,
+	value: MaybeUninit<T>
+}
+
+impl<T> FFIOption<T> {
+	pub fn some(x: T) -> Self {
+		Self { is_some: true, value: MaybeUninit::new(x) }
+	}
+
+	pub fn none() -> Self {
+		Self { is_some: false, value: MaybeUninit::uninit() }
+	}
+
+	pub fn from_std(x: Option<T>) -> Self {
+		match x {
+			Some(x) => Self::some(x),
+			None => Self::none()
+		}
+	}
+
+	pub fn is_some(&self) -> bool { self.is_some }
+
+	pub fn as_ref(&self) -> Option<&T> {
+		// SAFETY: the value is initialized when `is_some` is set
+		if self.is_some { Some(unsafe { self.value.assume_init_ref() }) } else { None }
+	}
+
+	pub fn as_mut(&mut self) -> Option<&mut T> {
+		if self.is_some { Some(unsafe { self.value.assume_init_mut() }) } else { None }
+	}
+
+	/// # Standard Equivalent
+	/// This function is equivalent to the identity function on `Option<T>`
+	pub fn into_option(self) -> Option<T> {
+		let x = ManuallyDrop::new(self);
+		if x.is_some { Some(unsafe { x.value.assume_init_read() }) } else { None }
+	}
+}
+
+impl<T> From<Option<T>> for FFIOption<T> {
+	fn from(x: Option<T>) -> Self {
+		Self::from_std(x)
+	}
+}
+
+impl<T> From<FFIOption<T>> for Option<T> {
+	fn from(x: FFIOption<T>) -> Self {
+		x.into_option()
+	}
+}
+
+impl<T: Clone> Clone for FFIOption<T> {
+	fn clone(&self) -> Self {
+		Self::from_std(self.as_ref().cloned())
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for FFIOption<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.as_ref().fmt(f)
+	}
+}
+
+impl<T> Drop for FFIOption<T> {
+	fn drop(&mut self) {
+		if self.is_some {
+			unsafe { self.value.assume_init_drop() }
+		}
+	}
+}
+
+#[repr(C)]
+union FFIResultValue<T, E> {
+	ok: ManuallyDrop<T>,
+	err: ManuallyDrop<E>
+}
+
+/// An FFI-safe equivalent of `Result<T, E>`, where `value` holds the `ok`
+/// value if `is_ok` is `true` and the `err` value otherwise.
+#[repr(C)]
+pub struct FFIResult<T, E> {
+	is_ok: bool,
+	value: FFIResultValue<T, E>
+}
+
+impl<T, E> FFIResult<T, E> {
+	pub fn ok(x: T) -> Self {
+		Self { is_ok: true, value: FFIResultValue { ok: ManuallyDrop::new(x) } }
+	}
+
+	pub fn err(x: E) -> Self {
+		Self { is_ok: false, value: FFIResultValue { err: ManuallyDrop::new(x) } }
+	}
+
+	pub fn from_std(x: Result<T, E>) -> Self {
+		match x {
+			Ok(x) => Self::ok(x),
+			Err(x) => Self::err(x)
+		}
+	}
+
+	pub fn is_ok(&self) -> bool { self.is_ok }
+
+	pub fn as_ref(&self) -> Result<&T, &E> {
+		// SAFETY: `is_ok` says which field is initialized
+		unsafe { if self.is_ok { Ok(&self.value.ok) } else { Err(&self.value.err) } }
+	}
+
+	pub fn as_mut(&mut self) -> Result<&mut T, &mut E> {
+		unsafe { if self.is_ok { Ok(&mut self.value.ok) } else { Err(&mut self.value.err) } }
+	}
+
+	/// # Standard Equivalent
+	/// This function is equivalent to the identity function on `Result<T, E>`
+	pub fn into_result(self) -> Result<T, E> {
+		let x = ManuallyDrop::new(self);
+		unsafe {
+			if x.is_ok { Ok(ptr::read(&*x.value.ok)) } else { Err(ptr::read(&*x.value.err)) }
+		}
+	}
+}
+
+impl<T, E> From<Result<T, E>> for FFIResult<T, E> {
+	fn from(x: Result<T, E>) -> Self {
+		Self::from_std(x)
+	}
+}
+
+impl<T, E> From<FFIResult<T, E>> for Result<T, E> {
+	fn from(x: FFIResult<T, E>) -> Self {
+		x.into_result()
+	}
+}
+
+impl<T: Clone, E: Clone> Clone for FFIResult<T, E> {
+	fn clone(&self) -> Self {
+		Self::from_std(self.as_ref().map(T::clone).map_err(E::clone))
+	}
+}
+
+impl<T: fmt::Debug, E: fmt::Debug> fmt::Debug for FFIResult<T, E> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.as_ref().fmt(f)
+	}
+}
+
+impl<T, E> Drop for FFIResult<T, E> {
+	fn drop(&mut self) {
+		unsafe {
+			if self.is_ok { ManuallyDrop::drop(&mut self.value.ok) } else { ManuallyDrop::drop(&mut self.value.err) }
+		}
+	}
+}
@@ -0,0 +1,156 @@
+use core::ptr::NonNull;
+
+use crate::{FFITrait, FFIDynPtr, FFIDynNonNull, FFIDynRef, FFIDynMut, FFITypeId, GenericVtable, GenericVtableLayout, VtableFor};
+
+/// An object whose vtable is only known to begin with a `GenericVtable`,
+/// as returned by `query_interface` before it's cast to the queried trait.
+///
+/// # Standard Equivalent
+/// This trait is a stand-in for `IUnknown` in COM
+pub trait FFIUnknown {}
+
+impl FFITrait for dyn FFIUnknown + '_ {
+	type Vtable = FFIUnknownVtable;
+}
+
+/// The vtable of `dyn FFIUnknown`, of which only the common part is known
+#[derive(Debug, Copy, Clone)]
+#[repr(transparent)]
+pub struct FFIUnknownVtable(pub GenericVtable);
+
+unsafe impl GenericVtableLayout for FFIUnknownVtable {}
+
+/// The function in the `query_interface` slot of a vtable. It returns the
+/// object as the interface with the given id, or null if it doesn't provide it.
+pub type QueryInterfaceFn = unsafe extern "C" fn(NonNull<()>, FFITypeId) -> FFIDynPtr<dyn FFIUnknown>;
+
+/// Implemented on every `#[ffi_trait]` object, identifying it to
+/// `query_interface`. The id is a hash of the crate's and the trait's name,
+/// and is the same as `Trait_interface_id()` of the C header.
+///
+/// # Safety
+/// No two traits may have the same id.
+pub unsafe trait Interface: FFITrait {
+	const ID: FFITypeId;
+}
+
+/// Implemented on the types which implement `#[ffi_trait(query_interface)]`
+/// traits, which is done with `#[ffi_trait(provides(Other, ...))]` on one of
+/// their impls.
+///
+/// # Safety
+/// `query_interface` must return null or this object as the interface with
+/// the id, with a vtable of that interface.
+#[diagnostic::on_unimplemented(
+	message = "`{Self}` doesn't say which interfaces it provides",
+	note = "add `#[ffi_trait(provides(...))]` to one of its impls of an ffi_trait, listing the other ffi_traits it can be queried for"
+)]
+pub unsafe trait QueryInterface {
+	fn query_interface(&self, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown>;
+}
+
+fn query_raw<T: FFITrait + ?Sized>(x: FFIDynNonNull<T>, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+	match T::query_interface_fn(unsafe { x.vtable.as_ref() }) {
+		Some(f) => unsafe { f(x.data, id) },
+		None => FFIDynPtr::null()
+	}
+}
+
+// the wrappers forward queries to the object they hold. they aren't recommended,
+// so that an implementor without `provides` isn't told about them
+#[diagnostic::do_not_recommend]
+unsafe impl<T: FFITrait + ?Sized> QueryInterface for FFIDynRef<'_, T> {
+	fn query_interface(&self, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+		query_raw::<T>(FFIDynNonNull::from_raw_parts(self.data(), NonNull::from(self.vtable())), id)
+	}
+}
+
+#[diagnostic::do_not_recommend]
+unsafe impl<T: FFITrait + ?Sized> QueryInterface for FFIDynMut<'_, T> {
+	fn query_interface(&self, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+		query_raw::<T>(FFIDynNonNull::from_raw_parts(self.data(), NonNull::from(self.vtable())), id)
+	}
+}
+
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend]
+unsafe impl<T: FFITrait + ?Sized> QueryInterface for crate::FFIDynBox<T> {
+	fn query_interface(&self, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+		query_raw::<T>(FFIDynNonNull::from_raw_parts(self.data(), NonNull::from(self.vtable())), id)
+	}
+}
+
+#[diagnostic::do_not_recommend]
+unsafe impl<T: FFITrait + ?Sized> QueryInterface for crate::FFIDynThinRef<'_, T> {
+	fn query_interface(&self, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+		query_raw::<T>(FFIDynNonNull::from_raw_parts(self.data(), NonNull::from(self.vtable())), id)
+	}
+}
+
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend]
+unsafe impl<T: FFITrait + ?Sized> QueryInterface for crate::FFIDynThinBox<T> {
+	fn query_interface(&self, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+		query_raw::<T>(FFIDynNonNull::from_raw_parts(self.data(), NonNull::from(self.vtable())), id)
+	}
+}
+
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend]
+unsafe impl<T: FFITrait + ?Sized> QueryInterface for crate::FFIDynArc<T> {
+	fn query_interface(&self, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+		query_raw::<T>(FFIDynNonNull::from_raw_parts(self.data(), NonNull::from(self.vtable())), id)
+	}
+}
+
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend]
+unsafe impl<T: FFITrait + ?Sized> QueryInterface for crate::FFIDynRc<T> {
+	fn query_interface(&self, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+		query_raw::<T>(FFIDynNonNull::from_raw_parts(self.data(), NonNull::from(self.vtable())), id)
+	}
+}
+
+impl<'a, T: FFITrait + ?Sized + 'static> FFIDynRef<'a, T> {
+	/// Asks the object whether it also implements `U`, which only objects of
+	/// `#[ffi_trait(query_interface)]` traits can answer.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `IUnknown::QueryInterface` in COM
+	pub fn query<U: Interface + ?Sized + 'static>(self) -> Option<FFIDynRef<'a, U>> {
+		let x = FFIDynNonNull::new(self.query_interface(U::ID))?;
+		Some(unsafe { FFIDynRef::from_raw_parts(x.data, x.vtable.cast()) })
+	}
+}
+
+impl<'a, T: FFITrait + ?Sized + 'static> FFIDynMut<'a, T> {
+	/// Asks the object whether it also implements `U`.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `IUnknown::QueryInterface` in COM
+	pub fn query<U: Interface + ?Sized + 'static>(&self) -> Option<FFIDynRef<'_, U>> {
+		let x = FFIDynNonNull::new(self.query_interface(U::ID))?;
+		Some(unsafe { FFIDynRef::from_raw_parts(x.data, x.vtable.cast()) })
+	}
+
+	/// Asks the object whether it also implements `U`, giving back a mutable
+	/// reference if it does.
+	pub fn query_mut<U: Interface + ?Sized + 'static>(self) -> Option<FFIDynMut<'a, U>> {
+		let x = FFIDynNonNull::new(self.query_interface(U::ID))?;
+		Some(unsafe { FFIDynMut::from_raw_parts(x.data, x.vtable.cast()) })
+	}
+}
+
+// used by the proc macro for `#[ffi_trait(provides(...))]`
+#[doc(hidden)]
+#[allow(non_snake_case)]
+pub fn __ffi_trait__provide<I: VtableFor<T> + ?Sized, T>(x: &T) -> FFIDynPtr<dyn FFIUnknown> {
+	FFIDynPtr::from_raw_parts(x as *const T as *mut (), NonNull::from(I::VTABLE).cast())
+}
+
+// used by the proc macro to fill in the `query_interface` slot
+#[doc(hidden)]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn __ffi_trait__query_interface<T: QueryInterface>(this: NonNull<()>, id: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> {
+	(*(this.as_ptr() as *const T)).query_interface(id)
+}
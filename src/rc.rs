@@ -0,0 +1,293 @@
+use alloc::rc::{self, Rc};
+use alloc::sync::{self, Arc};
+
+use core::marker::PhantomData;
+use core::mem::{self, ManuallyDrop};
+use core::ptr::NonNull;
+
+use crate::{FFITrait, FFIDynNonNull, FFIDynRef, IntoTraitObject, Upcast};
+
+/// The functions managing the reference counts of a shared allocation,
+/// which are separate from the vtable since the same type can be shared
+/// by an `Arc`, an `Rc`, or an allocation made by foreign code.
+///
+/// Weak references point to the same data as strong ones, which is
+/// dropped when the strong count reaches zero. The allocation itself
+/// stays until the weak count does too.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct FFIRcVtable {
+	/// Increments the strong count
+	pub retain: unsafe extern "C" fn(*const ()),
+	/// Decrements the strong count, dropping the data when it reaches zero
+	pub release: unsafe extern "C" fn(*const ()),
+	/// Increments the weak count, given a strong reference
+	pub downgrade: unsafe extern "C" fn(*const ()),
+	/// Increments the strong count given a weak reference, unless the
+	/// data was already dropped. Returns whether it did.
+	pub upgrade: unsafe extern "C" fn(*const ()) -> bool,
+	/// Increments the weak count, given a weak reference
+	pub retain_weak: unsafe extern "C" fn(*const ()),
+	/// Decrements the weak count, freeing the allocation when both counts are zero
+	pub release_weak: unsafe extern "C" fn(*const ())
+}
+
+macro_rules! rc_fns {
+	($vtable:ident, $std:ident, $weak:path) => {
+		struct $vtable<T>(PhantomData<T>);
+
+		impl<T> $vtable<T> {
+			const VTABLE: &'static FFIRcVtable = &FFIRcVtable {
+				retain: Self::retain,
+				release: Self::release,
+				downgrade: Self::downgrade,
+				upgrade: Self::upgrade,
+				retain_weak: Self::retain_weak,
+				release_weak: Self::release_weak
+			};
+
+			unsafe extern "C" fn retain(x: *const ()) {
+				$std::increment_strong_count(x as *const T);
+			}
+
+			unsafe extern "C" fn release(x: *const ()) {
+				$std::decrement_strong_count(x as *const T);
+			}
+
+			unsafe extern "C" fn downgrade(x: *const ()) {
+				let strong = ManuallyDrop::new($std::from_raw(x as *const T));
+				mem::forget($std::downgrade(&strong));
+			}
+
+			unsafe extern "C" fn upgrade(x: *const ()) -> bool {
+				let weak: ManuallyDrop<$weak> = ManuallyDrop::new(<$weak>::from_raw(x as *const T));
+				match weak.upgrade() {
+					Some(strong) => {
+						mem::forget(strong);
+						true
+					},
+					None => false
+				}
+			}
+
+			unsafe extern "C" fn retain_weak(x: *const ()) {
+				let weak: ManuallyDrop<$weak> = ManuallyDrop::new(<$weak>::from_raw(x as *const T));
+				mem::forget((*weak).clone());
+			}
+
+			unsafe extern "C" fn release_weak(x: *const ()) {
+				drop(<$weak>::from_raw(x as *const T));
+			}
+		}
+	};
+}
+
+rc_fns!(ArcVtable, Arc, sync::Weak<T>);
+rc_fns!(RcVtable, Rc, rc::Weak<T>);
+
+macro_rules! rc_type {
+	($(#[$attr:meta])* $name:ident, $(#[$weakattr:meta])* $weak:ident, $std:ident, $vtable:ident) => {
+		$(#[$attr])*
+		///
+		/// The handle implements the traits without `&mut self` methods, and `as_ref`
+		/// borrows it as an `FFIDynRef` for as long as the handle lives.
+		#[repr(C)]
+		pub struct $name<T: FFITrait + ?Sized> {
+			ptr: FFIDynNonNull<T>,
+			rc: NonNull<FFIRcVtable>,
+			_marker: PhantomData<T>
+		}
+
+		impl<T: FFITrait + ?Sized, U: IntoTraitObject<T>> From<$std<U>> for $name<T> {
+			fn from(x: $std<U>) -> Self {
+				$name::from_std(x)
+			}
+		}
+
+		impl<T: FFITrait + ?Sized> Clone for $name<T> {
+			fn clone(&self) -> Self {
+				unsafe {
+					(self.rc.as_ref().retain)(self.ptr.data.as_ptr());
+				}
+				Self { ptr: self.ptr, rc: self.rc, _marker: PhantomData }
+			}
+		}
+
+		impl<T: FFITrait + ?Sized> Drop for $name<T> {
+			fn drop(&mut self) {
+				unsafe {
+					(self.rc.as_ref().release)(self.ptr.data.as_ptr());
+				}
+			}
+		}
+
+		impl<T: FFITrait + ?Sized> $name<T> {
+			#[doc = concat!("Moves `x` into a new `", stringify!($std), "` and wraps it.")]
+			///
+			/// # Standard Equivalent
+			#[doc = concat!("This function is equivalent to `", stringify!($std), "::new(x) as ", stringify!($std), "<dyn T>`")]
+			pub fn new<U: IntoTraitObject<T>>(x: U) -> Self {
+				Self::from_std($std::new(x))
+			}
+
+			pub fn from_std<U: IntoTraitObject<T>>(x: $std<U>) -> Self {
+				let data = unsafe { NonNull::new_unchecked($std::into_raw(x) as *mut ()) };
+				unsafe {
+					Self::from_raw_parts(FFIDynNonNull::from_raw_parts(data, NonNull::from(U::VTABLE)), $vtable::<U>::VTABLE)
+				}
+			}
+
+			/// Creates a handle from its parts, taking over one strong reference.
+			///
+			/// # Safety
+			/// `ptr` must be valid for as long as the strong count is above zero,
+			/// and `rc` must manage the reference count of its data. The count
+			/// of an `FFIDynArc` of a `Send` and `Sync` object must be atomic.
+			pub unsafe fn from_raw_parts(ptr: FFIDynNonNull<T>, rc: &'static FFIRcVtable) -> Self {
+				Self { ptr, rc: NonNull::from(rc), _marker: PhantomData }
+			}
+
+			/// Gives up the handle without releasing its strong reference.
+			pub fn into_raw_parts(x: Self) -> (FFIDynNonNull<T>, &'static FFIRcVtable) {
+				let x = ManuallyDrop::new(x);
+				(x.ptr, unsafe { &*x.rc.as_ptr() })
+			}
+
+			/// Borrows the shared data.
+			///
+			/// # Standard Equivalent
+			/// This function is equivalent to `&*x`
+			pub fn as_ref(&self) -> FFIDynRef<'_, T> {
+				unsafe { self.ptr.to_ref() }
+			}
+
+			pub fn data(&self) -> NonNull<()> { self.ptr.data }
+
+			pub fn vtable(&self) -> &T::Vtable { unsafe { self.ptr.vtable.as_ref() } }
+
+			pub fn vtable_ptr(&self) -> NonNull<T::Vtable> { self.ptr.vtable }
+
+			pub fn rc_vtable(&self) -> &FFIRcVtable { unsafe { self.rc.as_ref() } }
+
+			/// Whether both handles point to the same allocation.
+			///
+			/// # Standard Equivalent
+			#[doc = concat!("This function is equivalent to `", stringify!($std), "::ptr_eq`")]
+			pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+				a.ptr.data == b.ptr.data
+			}
+
+			/// Creates a weak reference to the data.
+			///
+			/// # Standard Equivalent
+			#[doc = concat!("This function is equivalent to `", stringify!($std), "::downgrade`")]
+			pub fn downgrade(x: &Self) -> $weak<T> {
+				unsafe {
+					(x.rc.as_ref().downgrade)(x.ptr.data.as_ptr());
+				}
+				$weak { ptr: x.ptr, rc: x.rc, _marker: PhantomData }
+			}
+
+			/// Converts this into a handle to a supertrait object.
+			///
+			/// # Standard Equivalent
+			#[doc = concat!("This function is equivalent to `", stringify!($std), "<dyn T> as ", stringify!($std), "<dyn U>`")]
+			pub fn upcast<U: FFITrait + ?Sized>(x: Self) -> $name<U> where T: Upcast<U> {
+				let vtable = unsafe { T::upcast_vtable(x.vtable_ptr()) };
+				let (ptr, rc) = Self::into_raw_parts(x);
+				unsafe { $name::from_raw_parts(FFIDynNonNull::from_raw_parts(ptr.data, vtable), rc) }
+			}
+		}
+
+		$(#[$weakattr])*
+		#[repr(C)]
+		pub struct $weak<T: FFITrait + ?Sized> {
+			ptr: FFIDynNonNull<T>,
+			rc: NonNull<FFIRcVtable>,
+			_marker: PhantomData<T>
+		}
+
+		impl<T: FFITrait + ?Sized> Clone for $weak<T> {
+			fn clone(&self) -> Self {
+				unsafe {
+					(self.rc.as_ref().retain_weak)(self.ptr.data.as_ptr());
+				}
+				Self { ptr: self.ptr, rc: self.rc, _marker: PhantomData }
+			}
+		}
+
+		impl<T: FFITrait + ?Sized> Drop for $weak<T> {
+			fn drop(&mut self) {
+				unsafe {
+					(self.rc.as_ref().release_weak)(self.ptr.data.as_ptr());
+				}
+			}
+		}
+
+		impl<T: FFITrait + ?Sized> $weak<T> {
+			/// Gets a strong handle, unless the data was already dropped.
+			///
+			/// # Standard Equivalent
+			#[doc = concat!("This function is equivalent to `Weak::upgrade` for `", stringify!($std), "`")]
+			pub fn upgrade(&self) -> Option<$name<T>> {
+				if unsafe { (self.rc.as_ref().upgrade)(self.ptr.data.as_ptr()) } {
+					Some($name { ptr: self.ptr, rc: self.rc, _marker: PhantomData })
+				} else {
+					None
+				}
+			}
+
+			/// Creates a weak handle from its parts, taking over one weak reference.
+			///
+			/// # Safety
+			/// `ptr` must point to an allocation whose weak count is above zero,
+			/// and `rc` must manage its reference counts.
+			pub unsafe fn from_raw_parts(ptr: FFIDynNonNull<T>, rc: &'static FFIRcVtable) -> Self {
+				Self { ptr, rc: NonNull::from(rc), _marker: PhantomData }
+			}
+
+			/// Gives up the handle without releasing its weak reference.
+			pub fn into_raw_parts(x: Self) -> (FFIDynNonNull<T>, &'static FFIRcVtable) {
+				let x = ManuallyDrop::new(x);
+				(x.ptr, unsafe { &*x.rc.as_ptr() })
+			}
+
+			/// Converts this into a weak handle to a supertrait object.
+			pub fn upcast<U: FFITrait + ?Sized>(x: Self) -> $weak<U> where T: Upcast<U> {
+				let vtable = unsafe { T::upcast_vtable(x.ptr.vtable) };
+				let (ptr, rc) = Self::into_raw_parts(x);
+				unsafe { $weak::from_raw_parts(FFIDynNonNull::from_raw_parts(ptr.data, vtable), rc) }
+			}
+		}
+	};
+}
+
+rc_type! {
+	/// An FFI-safe equivalent of `Arc<dyn T>`
+	///
+	/// Foreign code can clone and drop the handle through its `FFIRcVtable`.
+	FFIDynArc,
+	/// A weak reference to the data of an `FFIDynArc`
+	///
+	/// # Standard Equivalent
+	/// This type is equivalent to `sync::Weak<dyn T>`
+	FFIDynWeak, Arc, ArcVtable
+}
+
+rc_type! {
+	/// An FFI-safe equivalent of `Rc<dyn T>`
+	///
+	/// Foreign code can clone and drop the handle through its `FFIRcVtable`.
+	FFIDynRc,
+	/// A weak reference to the data of an `FFIDynRc`
+	///
+	/// # Standard Equivalent
+	/// This type is equivalent to `rc::Weak<dyn T>`
+	FFIDynRcWeak, Rc, RcVtable
+}
+
+// like `Arc<dyn T>`, whose count is atomic
+unsafe impl<T: FFITrait + Send + Sync + ?Sized> Send for FFIDynArc<T> {}
+unsafe impl<T: FFITrait + Send + Sync + ?Sized> Sync for FFIDynArc<T> {}
+unsafe impl<T: FFITrait + Send + Sync + ?Sized> Send for FFIDynWeak<T> {}
+unsafe impl<T: FFITrait + Send + Sync + ?Sized> Sync for FFIDynWeak<T> {}
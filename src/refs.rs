@@ -2,20 +2,30 @@ use core::mem;
 use core::ptr::NonNull;
 use core::marker::PhantomData;
 
-use crate::{FFITrait, FFIDynPtr, FFIDynNonNull, IntoTraitObjectRuntime};
+use crate::{FFITrait, FFIDynPtr, FFIDynNonNull, IntoTraitObjectRuntime, Upcast, VtableBuilder, VtableError};
 
 /// An FFI-safe equivalent of `&dyn T`
 #[repr(transparent)]
 pub struct FFIDynRef<'a, T: FFITrait + ?Sized>(FFIDynNonNull<T>, PhantomData<&'a T>);
 
+// like `&dyn T`, which can be sent to another thread if the object can be shared
+unsafe impl<T: FFITrait + Sync + ?Sized> Send for FFIDynRef<'_, T> {}
+unsafe impl<T: FFITrait + Sync + ?Sized> Sync for FFIDynRef<'_, T> {}
+
 impl<T: FFITrait + ?Sized> Copy for FFIDynRef<'_, T> {}
 impl<T: FFITrait + ?Sized> Clone for FFIDynRef<'_, T> {
 	fn clone(&self) -> Self { *self }
 }
 
 /// An FFI-safe equivalent of `&mut dyn T`
+///
+/// Unlike `&'a mut T` it doesn't require `T: 'a`, so that boxes of objects
+/// which don't live for `'static` can deref to a `FFIDynMut<'static, T>`.
 #[repr(transparent)]
-pub struct FFIDynMut<'a, T: FFITrait + ?Sized>(FFIDynNonNull<T>, PhantomData<&'a mut T>);
+pub struct FFIDynMut<'a, T: FFITrait + ?Sized>(FFIDynNonNull<T>, PhantomData<(&'a mut (), *mut T)>);
+
+unsafe impl<T: FFITrait + Send + ?Sized> Send for FFIDynMut<'_, T> {}
+unsafe impl<T: FFITrait + Sync + ?Sized> Sync for FFIDynMut<'_, T> {}
 
 impl<'a, T: FFITrait + IntoTraitObjectRuntime<T> + ?Sized> From<&'a T> for FFIDynRef<'a, T> {
 	fn from(x: &'a T) -> Self {
@@ -45,9 +55,69 @@ impl<'a, T: FFITrait + ?Sized> FFIDynRef<'a, T> {
 		Self::from_nonnull(FFIDynNonNull::from_raw_parts(data, vtable))
 	}
 
+	/// Creates an `FFIDynRef` from data and a vtable filled in by foreign
+	/// code, checking that none of the vtable's methods are null. The vtable
+	/// may be one made for an older version of the trait, which is shorter.
+	///
+	/// # Safety
+	/// `data` must be valid for `'a`, and calling any of the vtable's
+	/// functions with it must be sound, following the signature and
+	/// contract of the corresponding trait method. `vtable` must be valid
+	/// for `'a` and for reads of the `vtable_size` it gives, see
+	/// `VtableBuilder::validate_ptr`.
+	pub unsafe fn from_foreign(data: NonNull<()>, vtable: NonNull<T::Builder>) -> Result<Self, VtableError> where T: VtableBuilder {
+		Ok(Self::from_raw_parts(data, T::check_ptr(vtable)?))
+	}
+
 	pub fn as_ptr(&mut self) -> &mut FFIDynPtr<T> { unsafe { mem::transmute(self) } }
 
 	pub fn to_ptr(self) -> FFIDynPtr<T> { unsafe { mem::transmute(self) } }
+
+	pub fn data(&self) -> NonNull<()> { self.0.data }
+
+	pub fn vtable(&self) -> &T::Vtable { unsafe { self.0.vtable.as_ref() } }
+
+	/// The vtable, which may be shorter than `T::Vtable` if it came from `from_foreign`.
+	pub fn vtable_ptr(&self) -> NonNull<T::Vtable> { self.0.vtable }
+
+	/// Converts this into a reference to a supertrait object.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `&dyn T as &dyn U`
+	pub fn upcast<U: FFITrait + ?Sized>(self) -> FFIDynRef<'a, U> where T: Upcast<U> {
+		unsafe { FFIDynRef::from_raw_parts(self.data(), T::upcast_vtable(self.vtable_ptr())) }
+	}
+	// used by the proc macro to pass an `FFIDynRef` on as a `&dyn T`. only `FFIDynMut`
+	// implements traits with `&mut self` methods, and they can't be called through `&`
+	#[doc(hidden)]
+	#[allow(non_snake_case)]
+	pub fn __ffi_trait__as_mut(&self) -> &FFIDynMut<'a, T> {
+		unsafe { &*(self as *const Self as *const FFIDynMut<'a, T>) }
+	}
+
+	// used by the proc macro the same way for traits with methods taking `self`, which
+	// only `FFIDynBox` implements. they can't be called through `&` either, so the box
+	// is never dropped
+	#[cfg(feature = "boxed")]
+	#[doc(hidden)]
+	#[allow(non_snake_case)]
+	pub fn __ffi_trait__as_box(&self) -> &crate::FFIDynBox<T> {
+		unsafe { &*(self as *const Self as *const crate::FFIDynBox<T>) }
+	}
+}
+
+// used by the proc macro to pass an `FFIDynRef` on as a `&dyn T`, through a wrapper
+// which implements the trait. every ffi_trait picks one for itself. `Dyn` is the trait
+// object for `'a`, which `Self` outlives
+#[doc(hidden)]
+#[allow(non_camel_case_types, non_snake_case)]
+#[diagnostic::on_unimplemented(
+	message = "`&{Self}` can't be passed through the vtable",
+	note = "only `FFIDynBox` implements traits with methods taking `self`, which needs the `boxed` feature"
+)]
+pub trait __ffi_trait__AsDyn<'a>: FFITrait {
+	type Dyn: ?Sized + 'a;
+	fn __ffi_trait__as_dyn<'b>(x: &'b FFIDynRef<'a, Self>) -> &'b Self::Dyn;
 }
 
 impl<'a, T: FFITrait + ?Sized> FFIDynMut<'a, T> {
@@ -72,7 +142,35 @@ impl<'a, T: FFITrait + ?Sized> FFIDynMut<'a, T> {
 		Self::from_nonnull(FFIDynNonNull::from_raw_parts(data, vtable))
 	}
 
+	/// Creates an `FFIDynMut` from data and a vtable filled in by foreign
+	/// code, checking that none of the vtable's methods are null. The vtable
+	/// may be one made for an older version of the trait, which is shorter.
+	///
+	/// # Safety
+	/// `data` must be valid and not aliased for `'a`, and calling any of
+	/// the vtable's functions with it must be sound, following the
+	/// signature and contract of the corresponding trait method. `vtable`
+	/// must be valid for `'a` and for reads of the `vtable_size` it gives,
+	/// see `VtableBuilder::validate_ptr`.
+	pub unsafe fn from_foreign(data: NonNull<()>, vtable: NonNull<T::Builder>) -> Result<Self, VtableError> where T: VtableBuilder {
+		Ok(Self::from_raw_parts(data, T::check_ptr(vtable)?))
+	}
+
 	pub fn as_ptr(&mut self) -> &mut FFIDynPtr<T> { unsafe { mem::transmute(self) } }
 
 	pub fn to_ptr(self) -> FFIDynPtr<T> { unsafe { mem::transmute(self) } }
+
+	pub fn data(&self) -> NonNull<()> { self.0.data }
+
+	pub fn vtable(&self) -> &T::Vtable { unsafe { self.0.vtable.as_ref() } }
+
+	/// The vtable, which may be shorter than `T::Vtable` if it came from `from_foreign`.
+	pub fn vtable_ptr(&self) -> NonNull<T::Vtable> { self.0.vtable }
+	/// Converts this into a mutable reference to a supertrait object.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `&mut dyn T as &mut dyn U`
+	pub fn upcast<U: FFITrait + ?Sized>(self) -> FFIDynMut<'a, U> where T: Upcast<U> {
+		unsafe { FFIDynMut::from_raw_parts(self.data(), T::upcast_vtable(self.vtable_ptr())) }
+	}
 }
\ No newline at end of file
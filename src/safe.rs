@@ -0,0 +1,115 @@
+use core::marker::PhantomData;
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::num::*;
+use core::ptr::NonNull;
+
+use crate::{FFITrait, FFIDynPtr, FFIDynNonNull, FFIDynRef, FFIDynMut, FFIUnwindResult, FFITypeId, GenericVtable};
+
+/// A marker for types with a stable ABI, which can be passed to and returned
+/// from the methods of an ffi_trait. The `#[ffi_trait]` macro checks every
+/// argument and return type of a method which has an ABI.
+///
+/// Implement it on your own `#[repr(C)]` and `#[repr(transparent)]` types
+/// with `#[derive(FfiSafe)]`, which checks the representation and fields.
+///
+/// ```compile_fail
+/// use ffi_trait::*;
+///
+/// #[ffi_trait]
+/// trait Named {
+///     fn names(&self) -> Vec<String>;
+/// }
+/// ```
+///
+/// # Safety
+/// The type must have the same layout and calling convention as a C type.
+#[diagnostic::on_unimplemented(
+	message = "`{Self}` can't be passed across the FFI boundary",
+	label = "not FFI-safe",
+	note = "ffi_trait methods with an ABI may only use types implementing `FfiSafe`",
+	note = "use `#[derive(FfiSafe)]` on `#[repr(C)]` types, or a pointer"
+)]
+pub unsafe trait FfiSafe {}
+
+// none of these are recommended, since rustc would list them all under the notes above
+macro_rules! ffi_safe {
+	($($t:ty),*) => { $(#[diagnostic::do_not_recommend] unsafe impl FfiSafe for $t {})* };
+}
+
+ffi_safe!((), bool, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+ffi_safe!(NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroUsize);
+ffi_safe!(NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroIsize);
+ffi_safe!(Option<NonZeroU8>, Option<NonZeroU16>, Option<NonZeroU32>, Option<NonZeroU64>, Option<NonZeroUsize>);
+ffi_safe!(Option<NonZeroI8>, Option<NonZeroI16>, Option<NonZeroI32>, Option<NonZeroI64>, Option<NonZeroIsize>);
+ffi_safe!(GenericVtable, FFITypeId);
+
+// pointers to unsized types are fat, so only thin ones are allowed
+#[diagnostic::do_not_recommend] unsafe impl<T> FfiSafe for *const T {}
+#[diagnostic::do_not_recommend] unsafe impl<T> FfiSafe for *mut T {}
+#[diagnostic::do_not_recommend] unsafe impl<T> FfiSafe for &T {}
+#[diagnostic::do_not_recommend] unsafe impl<T> FfiSafe for &mut T {}
+#[diagnostic::do_not_recommend] unsafe impl<T> FfiSafe for NonNull<T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T> FfiSafe for Option<&T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T> FfiSafe for Option<&mut T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T> FfiSafe for Option<NonNull<T>> {}
+
+#[diagnostic::do_not_recommend] unsafe impl<T: ?Sized> FfiSafe for PhantomData<T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FfiSafe> FfiSafe for MaybeUninit<T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FfiSafe> FfiSafe for ManuallyDrop<T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FfiSafe> FfiSafe for FFIUnwindResult<T> {}
+
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for FFIDynPtr<T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for FFIDynNonNull<T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for FFIDynRef<'_, T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for FFIDynMut<'_, T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for crate::FFIDynThinRef<'_, T> {}
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for crate::FFIDynThinBox<T> {}
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for crate::FFIDynBox<T> {}
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for crate::FFIDynArc<T> {}
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for crate::FFIDynRc<T> {}
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for crate::FFIDynWeak<T> {}
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend] unsafe impl<T: FFITrait + ?Sized> FfiSafe for crate::FFIDynRcWeak<T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: crate::ComInterface + ?Sized> FfiSafe for crate::ComPtr<T> {}
+#[diagnostic::do_not_recommend] unsafe impl FfiSafe for crate::Guid {}
+
+#[diagnostic::do_not_recommend] unsafe impl FfiSafe for crate::FFIStr<'_> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FfiSafe> FfiSafe for crate::FFISlice<'_, T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FfiSafe> FfiSafe for crate::FFISliceMut<'_, T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FfiSafe> FfiSafe for crate::FFIOption<T> {}
+#[diagnostic::do_not_recommend] unsafe impl<T: FfiSafe, E: FfiSafe> FfiSafe for crate::FFIResult<T, E> {}
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend] unsafe impl FfiSafe for crate::FFIString {}
+#[cfg(feature = "boxed")]
+#[diagnostic::do_not_recommend] unsafe impl<T: FfiSafe> FfiSafe for crate::FFIVec<T> {}
+
+macro_rules! ffi_safe_fn {
+	(@abi $abi:literal; $($arg:ident),*) => {
+		#[diagnostic::do_not_recommend] unsafe impl<R: FfiSafe, $($arg: FfiSafe),*> FfiSafe for extern $abi fn($($arg),*) -> R {}
+		#[diagnostic::do_not_recommend] unsafe impl<R: FfiSafe, $($arg: FfiSafe),*> FfiSafe for unsafe extern $abi fn($($arg),*) -> R {}
+		#[diagnostic::do_not_recommend] unsafe impl<R: FfiSafe, $($arg: FfiSafe),*> FfiSafe for Option<extern $abi fn($($arg),*) -> R> {}
+		#[diagnostic::do_not_recommend] unsafe impl<R: FfiSafe, $($arg: FfiSafe),*> FfiSafe for Option<unsafe extern $abi fn($($arg),*) -> R> {}
+	};
+	($($arg:ident),*) => {
+		ffi_safe_fn!(@abi "C"; $($arg),*);
+		ffi_safe_fn!(@abi "C-unwind"; $($arg),*);
+	};
+}
+
+ffi_safe_fn!();
+ffi_safe_fn!(A);
+ffi_safe_fn!(A, B);
+ffi_safe_fn!(A, B, C);
+ffi_safe_fn!(A, B, C, D);
+ffi_safe_fn!(A, B, C, D, E);
+ffi_safe_fn!(A, B, C, D, E, F);
+
+// used by the proc macro to check the types in method signatures
+#[doc(hidden)]
+#[allow(non_snake_case)]
+pub const fn __ffi_trait__assert_ffi_safe<T: FfiSafe + ?Sized>() {}
@@ -0,0 +1,155 @@
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::{slice, str};
+
+/// An FFI-safe equivalent of `&[T]`. C code may use a null pointer for an empty slice.
+#[repr(C)]
+pub struct FFISlice<'a, T> {
+	ptr: *const T,
+	len: usize,
+	_marker: PhantomData<&'a [T]>
+}
+
+impl<T> Copy for FFISlice<'_, T> {}
+impl<T> Clone for FFISlice<'_, T> {
+	fn clone(&self) -> Self { *self }
+}
+
+impl<'a, T> FFISlice<'a, T> {
+	pub fn from_std(x: &'a [T]) -> Self {
+		Self { ptr: x.as_ptr(), len: x.len(), _marker: PhantomData }
+	}
+
+	/// # Safety
+	/// `ptr` must be null or valid for reading `len` elements for `'a`,
+	/// like for `slice::from_raw_parts`.
+	pub unsafe fn from_raw_parts(ptr: *const T, len: usize) -> Self {
+		Self { ptr, len, _marker: PhantomData }
+	}
+
+	/// # Standard Equivalent
+	/// This function is equivalent to the identity function on `&[T]`
+	pub fn as_slice(self) -> &'a [T] {
+		if self.len == 0 {
+			&[]
+		} else {
+			unsafe { slice::from_raw_parts(self.ptr, self.len) }
+		}
+	}
+}
+
+impl<'a, T> From<&'a [T]> for FFISlice<'a, T> {
+	fn from(x: &'a [T]) -> Self {
+		Self::from_std(x)
+	}
+}
+
+impl<T> Deref for FFISlice<'_, T> {
+	type Target = [T];
+
+	fn deref(&self) -> &[T] { self.as_slice() }
+}
+
+/// An FFI-safe equivalent of `&mut [T]`. C code may use a null pointer for an empty slice.
+#[repr(C)]
+pub struct FFISliceMut<'a, T> {
+	ptr: *mut T,
+	len: usize,
+	_marker: PhantomData<&'a mut [T]>
+}
+
+impl<'a, T> FFISliceMut<'a, T> {
+	pub fn from_std(x: &'a mut [T]) -> Self {
+		Self { ptr: x.as_mut_ptr(), len: x.len(), _marker: PhantomData }
+	}
+
+	/// # Safety
+	/// `ptr` must be null or valid for reading and writing `len` elements
+	/// for `'a`, and not aliased, like for `slice::from_raw_parts_mut`.
+	pub unsafe fn from_raw_parts(ptr: *mut T, len: usize) -> Self {
+		Self { ptr, len, _marker: PhantomData }
+	}
+
+	/// # Standard Equivalent
+	/// This function is equivalent to the identity function on `&mut [T]`
+	pub fn into_slice(self) -> &'a mut [T] {
+		if self.len == 0 {
+			&mut []
+		} else {
+			unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+		}
+	}
+}
+
+impl<'a, T> From<&'a mut [T]> for FFISliceMut<'a, T> {
+	fn from(x: &'a mut [T]) -> Self {
+		Self::from_std(x)
+	}
+}
+
+impl<T> Deref for FFISliceMut<'_, T> {
+	type Target = [T];
+
+	fn deref(&self) -> &[T] {
+		if self.len == 0 {
+			&[]
+		} else {
+			unsafe { slice::from_raw_parts(self.ptr, self.len) }
+		}
+	}
+}
+
+impl<T> DerefMut for FFISliceMut<'_, T> {
+	fn deref_mut(&mut self) -> &mut [T] {
+		if self.len == 0 {
+			&mut []
+		} else {
+			unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+		}
+	}
+}
+
+/// An FFI-safe equivalent of `&str`, which isn't null-terminated.
+/// C code may use a null pointer for an empty string.
+#[repr(C)]
+pub struct FFIStr<'a> {
+	ptr: *const u8,
+	len: usize,
+	_marker: PhantomData<&'a str>
+}
+
+impl Copy for FFIStr<'_> {}
+impl Clone for FFIStr<'_> {
+	fn clone(&self) -> Self { *self }
+}
+
+impl<'a> FFIStr<'a> {
+	pub fn from_std(x: &'a str) -> Self {
+		Self { ptr: x.as_ptr(), len: x.len(), _marker: PhantomData }
+	}
+
+	/// # Safety
+	/// `ptr` must be null or valid for reading `len` bytes for `'a`,
+	/// and they must be UTF-8.
+	pub unsafe fn from_raw_parts(ptr: *const u8, len: usize) -> Self {
+		Self { ptr, len, _marker: PhantomData }
+	}
+
+	/// # Standard Equivalent
+	/// This function is equivalent to the identity function on `&str`
+	pub fn as_str(self) -> &'a str {
+		unsafe { str::from_utf8_unchecked(FFISlice::from_raw_parts(self.ptr, self.len).as_slice()) }
+	}
+}
+
+impl<'a> From<&'a str> for FFIStr<'a> {
+	fn from(x: &'a str) -> Self {
+		Self::from_std(x)
+	}
+}
+
+impl Deref for FFIStr<'_> {
+	type Target = str;
+
+	fn deref(&self) -> &str { self.as_str() }
+}
@@ -0,0 +1,243 @@
+#[cfg(feature = "boxed")] use core::alloc::Layout;
+#[cfg(feature = "boxed")] use core::mem::ManuallyDrop;
+#[cfg(feature = "boxed")] use core::ptr;
+
+use core::mem;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+use crate::{FFITrait, FFIDynNonNull, FFIDynRef, generic};
+#[cfg(feature = "boxed")] use crate::{FFIDynBox, FFIDynMut, GenericVtable, IntoTraitObjectRuntime};
+
+/// The offset of the data in a thin object, which is the size of the vtable
+/// pointer rounded up to the data's alignment
+fn data_offset(align: usize) -> usize {
+	(mem::size_of::<*const ()>() + align - 1) & !(align - 1)
+}
+
+/// Finds the data and the vtable of a thin object
+unsafe fn fat<T: FFITrait + ?Sized>(this: NonNull<()>) -> FFIDynNonNull<T> {
+	let vtable = *(this.as_ptr() as *const NonNull<T::Vtable>);
+	let data = (this.as_ptr() as *mut u8).add(data_offset(generic(vtable.as_ref()).align));
+	FFIDynNonNull::from_raw_parts(NonNull::new_unchecked(data as *mut ()), vtable)
+}
+
+/// An FFI-safe equivalent of `&dyn T` which is a single pointer, to the vtable
+/// pointer of the object followed by its data at the data's alignment. This is
+/// what C APIs which only pass a `void *` along can hold.
+#[repr(transparent)]
+pub struct FFIDynThinRef<'a, T: FFITrait + ?Sized>(NonNull<()>, PhantomData<&'a T>);
+
+unsafe impl<T: FFITrait + Sync + ?Sized> Send for FFIDynThinRef<'_, T> {}
+unsafe impl<T: FFITrait + Sync + ?Sized> Sync for FFIDynThinRef<'_, T> {}
+
+impl<T: FFITrait + ?Sized> Copy for FFIDynThinRef<'_, T> {}
+impl<T: FFITrait + ?Sized> Clone for FFIDynThinRef<'_, T> {
+	fn clone(&self) -> Self { *self }
+}
+
+impl<'a, T: FFITrait + ?Sized> FFIDynThinRef<'a, T> {
+	/// Creates an `FFIDynThinRef` from a pointer to a thin object.
+	///
+	/// # Safety
+	/// `x` must point to a vtable pointer for `T` followed by data valid for `'a`,
+	/// as laid out by `FFIDynThinBox`.
+	pub unsafe fn from_raw(x: NonNull<()>) -> Self {
+		Self(x, PhantomData)
+	}
+
+	pub fn as_raw(self) -> NonNull<()> { self.0 }
+
+	/// Borrows the object through a fat pointer.
+	pub fn to_ref(self) -> FFIDynRef<'a, T> {
+		unsafe { fat::<T>(self.0).to_ref() }
+	}
+
+	pub fn data(&self) -> NonNull<()> { unsafe { fat::<T>(self.0).data } }
+
+	pub fn vtable(&self) -> &'a T::Vtable { unsafe { fat::<T>(self.0).vtable.as_ref() } }
+
+	pub fn vtable_ptr(&self) -> NonNull<T::Vtable> { unsafe { fat::<T>(self.0).vtable } }
+}
+
+/// An FFI-safe equivalent of `Box<dyn T>` which is a single pointer, to the
+/// vtable pointer in an allocation, followed by the data at the data's alignment.
+/// C code finds the data with `Trait_thin_get` of the C header, and frees the box
+/// with `Trait_thin_drop`, which calls the function stored right before the
+/// vtable pointer.
+///
+/// It converts from `FFIDynBox` by moving the data to a new allocation, and keeps
+/// the box's allocation until it's dropped, so that `into_box` can move the data
+/// back into it, whoever's vtable frees it.
+///
+/// # Standard Equivalent
+/// This type is equivalent to `ThinBox<dyn T>`
+#[cfg(feature = "boxed")]
+#[repr(transparent)]
+pub struct FFIDynThinBox<T: FFITrait + ?Sized>(NonNull<()>, PhantomData<T>);
+
+#[cfg(feature = "boxed")]
+unsafe impl<T: FFITrait + Send + ?Sized> Send for FFIDynThinBox<T> {}
+#[cfg(feature = "boxed")]
+unsafe impl<T: FFITrait + Sync + ?Sized> Sync for FFIDynThinBox<T> {}
+
+/// The size of what comes before the vtable pointer of a thin box: the allocation
+/// of the `FFIDynBox` it came from, if any, and the free function, rounded up so
+/// that the data stays aligned
+#[cfg(feature = "boxed")]
+fn prefix_size(align: usize) -> usize {
+	(2 * mem::size_of::<*const ()>() + align - 1) & !(align - 1)
+}
+
+#[cfg(feature = "boxed")]
+fn thin_layout(vt: &GenericVtable) -> Layout {
+	let header = Layout::new::<*const ()>();
+	Layout::from_size_align(prefix_size(vt.align) + data_offset(vt.align) + vt.size, vt.align.max(header.align())).unwrap()
+}
+
+/// The allocation of the `FFIDynBox` a thin box came from, or null
+#[cfg(feature = "boxed")]
+unsafe fn old_box(this: *mut ()) -> *mut *mut () {
+	(this as *mut *mut ()).sub(2)
+}
+
+/// Frees only the allocation of a thin box, which starts before its vtable pointer
+#[cfg(feature = "boxed")]
+unsafe fn thin_free(this: *mut ()) {
+	let vt = &**(this as *const *const GenericVtable);
+	alloc::alloc::dealloc((this as *mut u8).sub(prefix_size(vt.align)), thin_layout(vt));
+}
+
+/// Frees a thin box whose data was dropped, with the allocation it came from
+#[cfg(feature = "boxed")]
+unsafe extern "C" fn thin_dealloc(this: *mut ()) {
+	let old = *old_box(this);
+	if !old.is_null() {
+		if let Some(f) = (**(this as *const *const GenericVtable)).dealloc {
+			f(old);
+		}
+	}
+	thin_free(this);
+}
+
+#[cfg(feature = "boxed")]
+impl<T: FFITrait + ?Sized> FFIDynThinBox<T> {
+	/// Moves `x` onto the heap after its vtable pointer and wraps it.
+	pub fn new<U: IntoTraitObjectRuntime<T>>(x: U) -> Self {
+		let x = ManuallyDrop::new(x);
+		let vtable = NonNull::from(IntoTraitObjectRuntime::<T>::get_vt(&*x));
+		unsafe { Self::moving(NonNull::from(&*x).cast(), vtable, ptr::null_mut()) }
+	}
+
+	/// Moves the data into a new thin allocation, which frees `old` with the
+	/// vtable's `dealloc` once it's dropped, unless it's null.
+	unsafe fn moving(data: NonNull<()>, vtable: NonNull<T::Vtable>, old: *mut ()) -> Self {
+		let vt = generic(vtable.as_ref());
+		let layout = thin_layout(vt);
+		let start = alloc::alloc::alloc(layout);
+		if start.is_null() {
+			alloc::alloc::handle_alloc_error(layout);
+		}
+		let this = start.add(prefix_size(vt.align)) as *mut ();
+		old_box(this).write(old);
+		(this as *mut unsafe extern "C" fn(*mut ())).sub(1).write(thin_dealloc);
+		(this as *mut NonNull<T::Vtable>).write(vtable);
+		ptr::copy_nonoverlapping(data.as_ptr() as *const u8, (this as *mut u8).add(data_offset(vt.align)), vt.size);
+		Self(NonNull::new_unchecked(this), PhantomData)
+	}
+
+	/// Takes ownership of a thin object.
+	///
+	/// # Safety
+	/// `x` must come from `FFIDynThinBox::into_raw` with the same `T`.
+	pub unsafe fn from_raw(x: NonNull<()>) -> Self {
+		Self(x, PhantomData)
+	}
+
+	pub fn into_raw(b: Self) -> NonNull<()> {
+		ManuallyDrop::new(b).0
+	}
+
+	pub fn as_thin_ref(&self) -> FFIDynThinRef<'_, T> {
+		unsafe { FFIDynThinRef::from_raw(self.0) }
+	}
+
+	/// Borrows the contents of the box through a fat pointer.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `&*b`
+	pub fn as_ref(&self) -> FFIDynRef<'_, T> {
+		unsafe { fat::<T>(self.0).to_ref() }
+	}
+
+	/// Mutably borrows the contents of the box through a fat pointer.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `&mut *b`
+	pub fn as_mut(&mut self) -> FFIDynMut<'_, T> {
+		unsafe { fat::<T>(self.0).to_ref_mut() }
+	}
+
+	pub fn data(&self) -> NonNull<()> { unsafe { fat::<T>(self.0).data } }
+
+	pub fn vtable(&self) -> &T::Vtable { unsafe { fat::<T>(self.0).vtable.as_ref() } }
+
+	pub fn vtable_ptr(&self) -> NonNull<T::Vtable> { unsafe { fat::<T>(self.0).vtable } }
+
+	/// Moves the contents into an `FFIDynBox`: back into the allocation of the box
+	/// this came from, or into one from the global allocator for the size and
+	/// alignment in the vtable, which the vtables of `new` free.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to `Box::from(b)`
+	pub fn into_box(b: Self) -> FFIDynBox<T> {
+		let this = Self::into_raw(b);
+		unsafe {
+			let x = fat::<T>(this);
+			let vt = generic(x.vtable.as_ref());
+			let old = *old_box(this.as_ptr()) as *mut u8;
+			// like `Box`, zero-sized data isn't allocated
+			let data = if !old.is_null() {
+				old
+			} else if vt.size == 0 {
+				vt.align as *mut u8
+			} else {
+				let layout = Layout::from_size_align(vt.size, vt.align).unwrap();
+				let data = alloc::alloc::alloc(layout);
+				if data.is_null() {
+					alloc::alloc::handle_alloc_error(layout);
+				}
+				data
+			};
+			ptr::copy_nonoverlapping(x.data.as_ptr() as *const u8, data, vt.size);
+			thin_free(this.as_ptr());
+			FFIDynBox::from_nonnull(FFIDynNonNull::from_raw_parts(NonNull::new_unchecked(data as *mut ()), x.vtable))
+		}
+	}
+}
+
+#[cfg(feature = "boxed")]
+impl<T: FFITrait + ?Sized> Drop for FFIDynThinBox<T> {
+	fn drop(&mut self) {
+		unsafe {
+			crate::drop_in_place_nonnull(fat::<T>(self.0));
+			thin_dealloc(self.0.as_ptr());
+		}
+	}
+}
+
+#[cfg(feature = "boxed")]
+impl<T: FFITrait + ?Sized> From<FFIDynBox<T>> for FFIDynThinBox<T> {
+	fn from(b: FFIDynBox<T>) -> Self {
+		let x = FFIDynBox::into_nonnull(b);
+		// the data is moved out, and the old allocation freed with the thin box
+		unsafe { Self::moving(x.data, x.vtable, x.data.as_ptr()) }
+	}
+}
+
+#[cfg(feature = "boxed")]
+impl<T: FFITrait + ?Sized> From<FFIDynThinBox<T>> for FFIDynBox<T> {
+	fn from(b: FFIDynThinBox<T>) -> Self {
+		FFIDynThinBox::into_box(b)
+	}
+}
@@ -0,0 +1,77 @@
+use core::mem::MaybeUninit;
+
+/// The FFI-safe result of calling a method of an ffi_trait with
+/// `on_panic = "catch"`, which records whether the method panicked.
+/// `value` is initialized if and only if `panicked` is `false`.
+///
+/// # Standard Equivalent
+/// This type is equivalent to `std::thread::Result<T>`, without the panic payload
+#[repr(C)]
+pub struct FFIUnwindResult<T> {
+	pub panicked: bool,
+	pub value: MaybeUninit<T>
+}
+
+impl<T> FFIUnwindResult<T> {
+	pub fn ok(x: T) -> Self {
+		Self { panicked: false, value: MaybeUninit::new(x) }
+	}
+
+	pub fn panicked() -> Self {
+		Self { panicked: true, value: MaybeUninit::uninit() }
+	}
+
+	pub fn into_option(self) -> Option<T> {
+		if self.panicked {
+			None
+		} else {
+			// SAFETY: the value is initialized when there was no panic
+			Some(unsafe { self.value.assume_init() })
+		}
+	}
+}
+
+impl<T> From<FFIUnwindResult<T>> for Option<T> {
+	fn from(x: FFIUnwindResult<T>) -> Self {
+		x.into_option()
+	}
+}
+
+// used by the proc macro for `on_panic = "abort"`. dropping this while
+// unwinding panics again, which aborts
+#[doc(hidden)]
+#[allow(non_camel_case_types)]
+pub struct __ffi_trait__AbortOnUnwind;
+
+impl Drop for __ffi_trait__AbortOnUnwind {
+	fn drop(&mut self) {
+		panic!("an `on_panic = \"abort\"` ffi_trait method panicked, aborting");
+	}
+}
+
+// used by the proc macro for `on_panic = "catch"`
+#[cfg(feature = "std")]
+#[doc(hidden)]
+#[allow(non_snake_case)]
+pub fn __ffi_trait__catch<T>(f: impl FnOnce() -> T) -> FFIUnwindResult<T> {
+	// the object might be left in a broken state, but this is what
+	// `catch_unwind` at a thread boundary does too
+	match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+		Ok(x) => FFIUnwindResult::ok(x),
+		Err(_) => FFIUnwindResult::panicked()
+	}
+}
+
+#[cfg(feature = "std")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ffi_trait__catch {
+	($e:expr) => { $crate::unwind::__ffi_trait__catch(|| $e) };
+}
+
+#[cfg(not(feature = "std"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ffi_trait__catch {
+	($e:expr) => { compile_error!("`on_panic = \"catch\"` needs the `std` feature of `ffi_trait`") };
+}
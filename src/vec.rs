@@ -0,0 +1,133 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::{ptr, str};
+
+use crate::{FFISlice, FFISliceMut};
+
+/// An FFI-safe equivalent of `Vec<T>`. It's freed by its `drop` function, so that
+/// the buffer goes back to the allocator it came from.
+#[repr(C)]
+pub struct FFIVec<T> {
+	ptr: *mut T,
+	len: usize,
+	cap: usize,
+	drop: unsafe extern "C" fn(*mut T, usize, usize)
+}
+
+unsafe extern "C" fn drop_vec<T>(ptr: *mut T, len: usize, cap: usize) {
+	drop(Vec::from_raw_parts(ptr, len, cap));
+}
+
+impl<T> FFIVec<T> {
+	pub fn from_std(x: Vec<T>) -> Self {
+		let mut x = ManuallyDrop::new(x);
+		Self { ptr: x.as_mut_ptr(), len: x.len(), cap: x.capacity(), drop: drop_vec::<T> }
+	}
+
+	/// # Safety
+	/// `ptr` must be null or valid for reading and writing `len` initialized
+	/// elements, and owned by the returned vector until it calls `drop` with
+	/// `ptr`, its length and `cap`. A length of 0 only frees the buffer.
+	pub unsafe fn from_raw_parts(ptr: *mut T, len: usize, cap: usize, drop: unsafe extern "C" fn(*mut T, usize, usize)) -> Self {
+		Self { ptr, len, cap, drop }
+	}
+
+	/// Reuses the buffer if it came from the allocator of this crate, and
+	/// moves the elements to a new one otherwise.
+	///
+	/// # Standard Equivalent
+	/// This function is equivalent to the identity function on `Vec<T>`
+	pub fn into_vec(self) -> Vec<T> {
+		let x = ManuallyDrop::new(self);
+		unsafe {
+			if core::ptr::fn_addr_eq(x.drop, drop_vec::<T> as unsafe extern "C" fn(*mut T, usize, usize)) {
+				return Vec::from_raw_parts(x.ptr, x.len, x.cap);
+			}
+			let mut v = Vec::with_capacity(x.len);
+			if x.len != 0 {
+				ptr::copy_nonoverlapping(x.ptr, v.as_mut_ptr(), x.len);
+				v.set_len(x.len);
+			}
+			(x.drop)(x.ptr, 0, x.cap);
+			v
+		}
+	}
+}
+
+impl<T> From<Vec<T>> for FFIVec<T> {
+	fn from(x: Vec<T>) -> Self {
+		Self::from_std(x)
+	}
+}
+
+impl<T> From<FFIVec<T>> for Vec<T> {
+	fn from(x: FFIVec<T>) -> Self {
+		x.into_vec()
+	}
+}
+
+impl<T> Deref for FFIVec<T> {
+	type Target = [T];
+
+	fn deref(&self) -> &[T] {
+		unsafe { FFISlice::from_raw_parts(self.ptr, self.len).as_slice() }
+	}
+}
+
+impl<T> DerefMut for FFIVec<T> {
+	fn deref_mut(&mut self) -> &mut [T] {
+		unsafe { FFISliceMut::from_raw_parts(self.ptr, self.len).into_slice() }
+	}
+}
+
+impl<T> Drop for FFIVec<T> {
+	fn drop(&mut self) {
+		unsafe { (self.drop)(self.ptr, self.len, self.cap) }
+	}
+}
+
+/// An FFI-safe equivalent of `String`, which isn't null-terminated. Like
+/// `FFIVec`, it's freed by its `drop` function.
+#[repr(transparent)]
+pub struct FFIString(FFIVec<u8>);
+
+impl FFIString {
+	pub fn from_std(x: String) -> Self {
+		Self(FFIVec::from_std(x.into_bytes()))
+	}
+
+	/// # Safety
+	/// Like `FFIVec::from_raw_parts`, and the bytes must be UTF-8.
+	pub unsafe fn from_raw_parts(ptr: *mut u8, len: usize, cap: usize, drop: unsafe extern "C" fn(*mut u8, usize, usize)) -> Self {
+		Self(FFIVec::from_raw_parts(ptr, len, cap, drop))
+	}
+
+	/// # Standard Equivalent
+	/// This function is equivalent to the identity function on `String`
+	pub fn into_string(self) -> String {
+		unsafe { String::from_utf8_unchecked(self.0.into_vec()) }
+	}
+}
+
+impl From<String> for FFIString {
+	fn from(x: String) -> Self {
+		Self::from_std(x)
+	}
+}
+
+impl From<FFIString> for String {
+	fn from(x: FFIString) -> Self {
+		x.into_string()
+	}
+}
+
+impl Deref for FFIString {
+	type Target = str;
+
+	fn deref(&self) -> &str {
+		unsafe { str::from_utf8_unchecked(&self.0) }
+	}
+}
@@ -0,0 +1,69 @@
+// boxed associated types are carried as an `FFIDynBox`
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+#[ffi_trait]
+pub trait Shape {
+	fn area(&self) -> u32;
+}
+
+#[ffi_trait(assoc(Unit = u32))]
+pub trait Factory {
+	const SIDES: u32;
+	const VERSION: u8 = 2;
+	type Unit;
+	type Made: Shape;
+
+	fn make(&self, size: Self::Unit) -> Self::Made;
+	fn unit(&self) -> Self::Unit;
+}
+
+struct Square(u32);
+
+impl Shape for Square {
+	fn area(&self) -> u32 { self.0 * self.0 }
+}
+
+struct Squares;
+
+#[ffi_trait]
+impl Factory for Squares {
+	const SIDES: u32 = 4;
+	type Unit = u32;
+	type Made = Square;
+
+	fn make(&self, size: u32) -> Square { Square(size) }
+	fn unit(&self) -> u32 { 1 }
+}
+
+// works the same with the implementor and with its wrappers
+fn total<F: Factory<Unit = u32>>(f: &F) -> u32 {
+	f.make(3).area() + f.unit()
+}
+
+#[test]
+fn through_vtable() {
+	let r = FFIDynRef::<dyn Factory>::from_std(&Squares as &dyn Factory);
+	assert_eq!(r.vtable().SIDES, 4);
+	assert_eq!(r.vtable().VERSION, 2);
+	assert_eq!(r.make(2).area(), 4);
+	assert_eq!(total(&Squares), 10);
+	assert_eq!(total(&r), 10);
+}
+
+#[test]
+fn builder() {
+	let b = <dyn Factory as VtableBuilder>::Builder::default();
+	assert_eq!(b.SIDES, 0);
+	assert_eq!(b.VERSION, 2);
+}
+
+#[test]
+fn header() {
+	let h = <dyn Factory as CHeader>::C_HEADER;
+	assert!(h.contains("#include \"Shape.h\""));
+	assert!(h.contains("\tuint32_t SIDES;\n\tuint8_t VERSION;\n"));
+	assert!(h.contains("\tShape_ptr (*make)(void *self, uint32_t arg0);"));
+	assert!(h.contains("\tuint32_t (*unit)(void *self);"));
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+use std::rc::Rc;
+use std::cell::Cell;
+
+#[ffi_trait]
+pub trait Counter {
+	fn get(&self) -> u32;
+	fn add(&mut self, x: u32, y: u32);
+}
+
+struct Tracked(u32, Rc<Cell<u32>>);
+
+impl Counter for Tracked {
+	fn get(&self) -> u32 { self.0 }
+	fn add(&mut self, x: u32, y: u32) { self.0 += x + y }
+}
+
+impl Drop for Tracked {
+	fn drop(&mut self) { self.1.set(self.1.get() + 1) }
+}
+
+#[test]
+fn call_and_drop() {
+	let drops = Rc::new(Cell::new(0));
+	let mut b: FFIDynBox<dyn Counter> = FFIDynBox::new(Tracked(1, drops.clone()));
+	b.add(2, 3);
+	assert_eq!(b.get(), 6);
+	assert_eq!((*b).get(), 6);
+	assert_eq!(b.as_mut().get(), 6);
+	drop(b);
+	assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn std_box_roundtrip() {
+	let drops = Rc::new(Cell::new(0));
+	let b = Box::new(Tracked(1, drops.clone()));
+	let data = &*b as *const Tracked as *const ();
+	let b: FFIDynBox<dyn Counter> = b.into();
+	// the original box comes back, rather than one holding the wrapper
+	let b: Box<dyn Counter> = b.into();
+	assert_eq!(&*b as *const dyn Counter as *const (), data);
+	assert_eq!(b.get(), 1);
+	drop(b);
+	assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn borrowing_box() {
+	struct Borrowed<'a>(&'a u32);
+	impl Counter for Borrowed<'_> {
+		fn get(&self) -> u32 { *self.0 }
+		fn add(&mut self, _: u32, _: u32) {}
+	}
+
+	let x = 5;
+	let b: FFIDynBox<dyn Counter + '_> = FFIDynBox::new(Borrowed(&x));
+	assert_eq!((*b).get(), 5);
+	let b: Box<dyn Counter + '_> = b.into();
+	assert_eq!(b.get(), 5);
+}
@@ -0,0 +1,62 @@
+use ffi_trait::*;
+
+mod common;
+use common::CDir;
+
+use std::os::raw::c_int;
+use std::ptr::NonNull;
+
+#[ffi_trait]
+pub trait Source {
+	fn read(&mut self, buf: *mut u8, len: usize) -> isize;
+	fn eof(&self) -> bool;
+}
+
+#[ffi_trait(query_interface)]
+pub trait File: Source {
+	fn seek(&mut self, pos: u64);
+	fn path(&self) -> *const NonNull<c_int>;
+	fn copy_to(&mut self, to: FFIDynMut<dyn Source>) -> u64;
+	#[ffi_trait(since = 2)]
+	fn truncate(&mut self, len: u64);
+}
+
+#[test]
+fn header_contents() {
+	let h = <dyn File as CHeader>::C_HEADER;
+	assert!(h.contains("#include \"Source.h\""));
+	assert!(h.contains("struct File_vtable {"));
+	assert!(h.contains("\tSource_vtable super_Source;"));
+	assert!(h.contains("\tvoid (*seek)(void *self, uint64_t arg0);"));
+	assert!(h.contains("\tint *const *(*path)(void *self);"));
+	assert!(h.contains("\tuint64_t (*copy_to)(void *self, Source_ptr arg0);"));
+	assert!(h.contains("static inline void File_seek(File_ptr self, uint64_t arg0) {"));
+	assert!(h.contains("static inline Source_ptr File_as_Source(File_ptr self) {"));
+
+	let h = <dyn Source as CHeader>::C_HEADER;
+	assert!(h.contains("\tptrdiff_t (*read)(void *self, uint8_t *arg0, size_t arg1);"));
+	assert!(h.contains("\treturn self.vtable->eof(self.data);"));
+}
+
+// checks the headers with the system C compiler
+#[test]
+fn c_header_compiles() {
+	let dir = CDir::new("c-header");
+	dir.write("Source.h", <dyn Source as CHeader>::C_HEADER);
+	dir.write("File.h", <dyn File as CHeader>::C_HEADER);
+	dir.check(concat!(
+		"#include \"File.h\"\n",
+		"_Static_assert(sizeof(File_ptr) == 2 * sizeof(void *), \"fat pointer\");\n",
+		"uint64_t f(File_ptr x) { File_seek(x, 1); if (File_has_truncate(x)) File_truncate(x, 0); return File_copy_to(x, File_as_Source(x)); }\n",
+		"void g(File_rc x) { File_rc y = File_rc_clone(x); File_seek(File_rc_get(y), 1); File_rc_drop(y); }\n",
+		"bool q(File_ptr x) { return File_query_interface(x, Source_interface_id()).data != NULL; }\n",
+		"bool t(void *thin) { bool eof = Source_eof(Source_thin_get(thin)); Source_thin_drop(thin); return eof; }\n",
+		"bool h(File_weak w) { File_rc x; if (!File_weak_upgrade(w, &x)) return false; File_weak_drop(File_rc_downgrade(x)); File_rc_drop(x); return true; }\n"
+	));
+}
+
+#[test]
+fn header_layout_matches() {
+	// the C vtable is the Rust one field for field, so its size must match
+	assert_eq!(std::mem::size_of::<<dyn File as FFITrait>::Vtable>(), std::mem::size_of::<usize>() * (7 + 8 + 4));
+}
@@ -0,0 +1,213 @@
+// the objects are made by `ComPtr::new`
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+mod common;
+use common::CDir;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+#[ffi_trait(layout = "com")]
+pub trait Counter {
+	fn add(&self, by: u32) -> u32;
+	fn get(&self) -> u32;
+}
+
+#[ffi_trait(layout = "com", iid = "6b29fc40-ca47-1067-b31d-00dd010662da")]
+pub trait Named {
+	fn name(&self) -> u32;
+}
+
+struct Native {
+	value: Cell<u32>,
+	dropped: Rc<Cell<bool>>
+}
+
+impl Counter for Native {
+	fn add(&self, by: u32) -> u32 {
+		self.value.set(self.value.get() + by);
+		self.value.get()
+	}
+
+	fn get(&self) -> u32 { self.value.get() }
+}
+
+impl Drop for Native {
+	fn drop(&mut self) {
+		self.dropped.set(true);
+	}
+}
+
+fn native() -> (ComPtr<dyn Counter>, Rc<Cell<bool>>) {
+	let dropped = Rc::new(Cell::new(false));
+	(ComPtr::new(Native { value: Cell::new(0), dropped: dropped.clone() }), dropped)
+}
+
+#[test]
+fn refcounting() {
+	let (c, dropped) = native();
+	assert_eq!(c.add(2), 2);
+	let d = c.clone();
+	assert_eq!(d.add(3), 5);
+	drop(c);
+	assert!(!dropped.get());
+	assert_eq!(d.get(), 5);
+	drop(d);
+	assert!(dropped.get());
+}
+
+#[test]
+fn query() {
+	let (c, dropped) = native();
+	let d = c.query::<dyn Counter>().unwrap();
+	assert_eq!(d.as_raw(), c.as_raw());
+	assert!(c.query::<dyn Named>().is_none());
+	drop(c);
+	assert!(!dropped.get());
+	drop(d);
+	assert!(dropped.get());
+}
+
+#[test]
+fn iid() {
+	assert_eq!(format!("{:?}", <dyn Named as ComInterface>::IID), "6B29FC40-CA47-1067-B31D-00DD010662DA");
+	// made from the interface id otherwise, as a version 8 UUID
+	let iid = <dyn Counter as ComInterface>::IID;
+	assert_eq!(iid.data3 >> 12, 8);
+	assert_eq!(iid.data4[0] >> 6, 2);
+	assert!(<dyn Counter as CHeader>::C_HEADER.contains(&format!("0x{:08x}, 0x{:04x}, 0x{:04x}", iid.data1, iid.data2, iid.data3)));
+}
+
+#[test]
+fn header() {
+	let h = <dyn Counter as CHeader>::C_HEADER;
+	assert!(h.contains("\tuint32_t (FFI_TRAIT_SYSTEM *AddRef)(Counter *self);"));
+	assert!(h.contains("\tuint32_t (FFI_TRAIT_SYSTEM *add)(Counter *self, uint32_t arg0);"));
+	assert!(h.contains("struct Counter {\n\tconst Counter_vtable *vtable;\n};"));
+	assert!(h.contains("static inline uint32_t Counter_get(Counter *self) {"));
+}
+
+// a counter implemented in C, which also calls the methods of a Rust one
+#[cfg(target_os = "linux")]
+const FIXTURE: &str = r#"
+#include <stdlib.h>
+#include <string.h>
+#include "Counter.h"
+
+typedef struct CCounter {
+	Counter base;
+	uint32_t refs;
+	uint32_t value;
+} CCounter;
+
+static int freed = 0;
+
+static int32_t FFI_TRAIT_SYSTEM query_interface(Counter *self, const ffi_trait_guid *iid, void **out) {
+	ffi_trait_guid own = Counter_iid();
+	if (memcmp(iid, &own, sizeof own) != 0) {
+		*out = NULL;
+		return (int32_t)0x80004002;
+	}
+	Counter_AddRef(self);
+	*out = self;
+	return 0;
+}
+
+static uint32_t FFI_TRAIT_SYSTEM add_ref(Counter *self) {
+	return ++((CCounter *)self)->refs;
+}
+
+static uint32_t FFI_TRAIT_SYSTEM release(Counter *self) {
+	uint32_t refs = --((CCounter *)self)->refs;
+	if (refs == 0) {
+		free(self);
+		freed++;
+	}
+	return refs;
+}
+
+static uint32_t FFI_TRAIT_SYSTEM add(Counter *self, uint32_t by) {
+	return ((CCounter *)self)->value += by;
+}
+
+static uint32_t FFI_TRAIT_SYSTEM get(Counter *self) {
+	return ((CCounter *)self)->value;
+}
+
+static const Counter_vtable vtable = { query_interface, add_ref, release, add, get };
+
+Counter *fixture_new(uint32_t value) {
+	CCounter *x = malloc(sizeof *x);
+	x->base.vtable = &vtable;
+	x->refs = 1;
+	x->value = value;
+	return &x->base;
+}
+
+uint32_t fixture_use(Counter *c) {
+	Counter *d;
+	ffi_trait_guid iid = Counter_iid();
+	if (Counter_QueryInterface(c, &iid, (void **)&d) != 0) return 0;
+	Counter_add(d, 10);
+	Counter_Release(d);
+	return Counter_get(c);
+}
+
+int fixture_freed(void) {
+	return freed;
+}
+"#;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+	fn dlopen(filename: *const std::os::raw::c_char, flag: std::os::raw::c_int) -> *mut std::os::raw::c_void;
+	fn dlsym(handle: *mut std::os::raw::c_void, symbol: *const std::os::raw::c_char) -> *mut std::os::raw::c_void;
+}
+
+// builds the fixture with the system C compiler
+#[cfg(target_os = "linux")]
+#[test]
+fn c_fixture() {
+	use std::ffi::CString;
+	use std::ptr::NonNull;
+
+	let dir = CDir::new("com");
+	dir.write("Counter.h", <dyn Counter as CHeader>::C_HEADER);
+	dir.write("fixture.c", FIXTURE);
+	dir.cc(&["-shared", "-fPIC", "-o", "libfixture.so", "fixture.c"]);
+
+	unsafe {
+		let path = CString::new(dir.join("libfixture.so").to_str().unwrap()).unwrap();
+		let lib = dlopen(path.as_ptr(), 2 /* RTLD_NOW */);
+		assert!(!lib.is_null());
+		let sym = |name: &str| {
+			let name = CString::new(name).unwrap();
+			let x = dlsym(lib, name.as_ptr());
+			assert!(!x.is_null());
+			x
+		};
+		let new: unsafe extern "C" fn(u32) -> NonNull<()> = std::mem::transmute(sym("fixture_new"));
+		let use_: unsafe extern "C" fn(NonNull<()>) -> u32 = std::mem::transmute(sym("fixture_use"));
+		let freed: unsafe extern "C" fn() -> std::os::raw::c_int = std::mem::transmute(sym("fixture_freed"));
+
+		// a C object used from Rust
+		let c = ComPtr::<dyn Counter>::from_raw(new(1));
+		assert_eq!(c.add(2), 3);
+		let d = c.query::<dyn Counter>().unwrap();
+		assert!(c.query::<dyn Named>().is_none());
+		drop(c);
+		assert_eq!(d.get(), 3);
+		assert_eq!(use_(d.as_raw()), 13);
+		assert_eq!(freed(), 0);
+		drop(d);
+		assert_eq!(freed(), 1);
+
+		// a Rust object used from C
+		let (c, dropped) = native();
+		assert_eq!(use_(c.as_raw()), 10);
+		drop(c);
+		assert!(dropped.get());
+	}
+}
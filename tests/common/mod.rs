@@ -0,0 +1,52 @@
+// compiles C code against the generated headers, for the tests which check them
+// not every test uses every helper
+#![allow(dead_code)]
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A temporary directory for C sources, removed when dropped
+pub struct CDir(PathBuf);
+
+impl CDir {
+	pub fn new(name: &str) -> Self {
+		let dir = std::env::temp_dir().join(format!("ffi-trait-{}-{}", name, std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		CDir(dir)
+	}
+
+	pub fn write(&self, file: &str, contents: &str) {
+		std::fs::write(self.0.join(file), contents).unwrap();
+	}
+
+	/// Runs the system C compiler in the directory. A missing compiler fails the
+	/// test, since the headers would go unchecked otherwise
+	pub fn cc(&self, args: &[&str]) {
+		let status = Command::new("cc")
+			.args(["-std=c11", "-Wall", "-Werror"])
+			.args(args)
+			.current_dir(&self.0)
+			.status()
+			.unwrap_or_else(|e| panic!("couldn't run `cc`, the C header tests need a C compiler ({}); skip them with `--skip c_`", e));
+		assert!(status.success(), "`cc {}` failed", args.join(" "));
+	}
+
+	/// Checks that `main.c` compiles
+	pub fn check(&self, main: &str) {
+		self.write("main.c", main);
+		self.cc(&["-fsyntax-only", "main.c"]);
+	}
+}
+
+impl Deref for CDir {
+	type Target = Path;
+
+	fn deref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for CDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
@@ -0,0 +1,9 @@
+// `not_send` needs `FFIDynBox`
+#![cfg(feature = "std")]
+
+// every construct which `#[ffi_trait]` and `#[derive(FfiSafe)]` reject
+#[test]
+fn compile_fail() {
+	let t = trybuild::TestCases::new();
+	t.compile_fail("tests/ui/*.rs");
+}
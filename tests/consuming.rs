@@ -0,0 +1,74 @@
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+use std::rc::Rc;
+use std::cell::Cell;
+
+#[ffi_trait]
+pub trait Task {
+	fn progress(&self) -> u32;
+	fn step(&mut self, by: u32);
+	fn finish(self: Box<Self>) -> u32;
+	fn cancel(self) -> u32;
+}
+
+struct Sum(u32, Rc<Cell<u32>>);
+
+impl Task for Sum {
+	fn progress(&self) -> u32 { self.0 }
+	fn step(&mut self, by: u32) { self.0 += by }
+	fn finish(self: Box<Self>) -> u32 { self.0 }
+	fn cancel(self) -> u32 { 0 }
+}
+
+impl Drop for Sum {
+	fn drop(&mut self) { self.1.set(self.1.get() + 1) }
+}
+
+// borrows a `Task`, which only `FFIDynBox` implements
+#[ffi_trait]
+pub trait Monitor {
+	fn check(&self, task: &dyn Task) -> u32;
+}
+
+struct Doubling;
+
+impl Monitor for Doubling {
+	fn check(&self, task: &dyn Task) -> u32 { task.progress() * 2 }
+}
+
+#[test]
+fn finish() {
+	let drops = Rc::new(Cell::new(0));
+	let mut b = FFIDynBox::<dyn Task>::new(Sum(1, drops.clone()));
+	b.step(2);
+	assert_eq!(Box::new(b).finish(), 3);
+	assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn cancel() {
+	let drops = Rc::new(Cell::new(0));
+	let b: Box<dyn Task> = Box::new(Sum(1, drops.clone()));
+	let b: FFIDynBox<dyn Task> = b.into();
+	assert_eq!(b.cancel(), 0);
+	assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn borrowed() {
+	let drops = Rc::new(Cell::new(0));
+	let task = Sum(3, drops.clone());
+	let m = FFIDynRef::<dyn Monitor>::from_std(&Doubling as &dyn Monitor);
+	assert_eq!(m.check(&task), 6);
+	assert_eq!(m.check(&FFIDynBox::<dyn Task>::new(Sum(4, drops.clone()))), 8);
+	assert_eq!(drops.get(), 1);
+	drop(task);
+	assert_eq!(drops.get(), 2);
+}
+
+#[test]
+fn header() {
+	assert!(<dyn Task as CHeader>::C_HEADER.contains("\tuint32_t (*finish)(void *self); /* takes ownership of self */"));
+}
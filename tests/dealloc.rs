@@ -0,0 +1,81 @@
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		LIVE.fetch_add(layout.size(), Ordering::SeqCst);
+		System.alloc(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		LIVE.fetch_sub(layout.size(), Ordering::SeqCst);
+		System.dealloc(ptr, layout)
+	}
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[ffi_trait]
+pub trait Value {
+	fn value(&self) -> u64;
+}
+
+impl Value for [u64; 4] {
+	fn value(&self) -> u64 { self.iter().sum() }
+}
+
+impl Value for () {
+	fn value(&self) -> u64 { 0 }
+}
+
+// the global allocator is shared between tests, so everything is checked in one test
+#[test]
+fn dealloc() {
+	let before = LIVE.load(Ordering::SeqCst);
+
+	// dropping the box frees the allocation
+	let b: FFIDynBox<dyn Value> = FFIDynBox::new([1, 2, 3, 4]);
+	assert_eq!(b.value(), 10);
+	assert!(b.vtable().__ffi_trait__dealloc.is_some());
+	drop(b);
+	assert_eq!(LIVE.load(Ordering::SeqCst), before);
+
+	// freeing through the vtable, as foreign code would
+	let p = FFIDynBox::into_raw(FFIDynBox::<dyn Value>::new([1, 2, 3, 4]));
+	unsafe {
+		drop_in_place_ptr(p);
+		dealloc_ptr(p);
+	}
+	assert_eq!(LIVE.load(Ordering::SeqCst), before);
+
+	// zero-sized types never allocate, so there's nothing to free
+	let b: FFIDynBox<dyn Value> = FFIDynBox::new(());
+	assert_eq!(b.value(), 0);
+	drop(b);
+	assert_eq!(LIVE.load(Ordering::SeqCst), before);
+
+	// converting to a thin box and back moves the data between allocations
+	let b = FFIDynThinBox::from(FFIDynBox::<dyn Value>::new([1, 2, 3, 4]));
+	assert_eq!(b.value(), 10);
+	let b = FFIDynThinBox::into_box(b);
+	assert_eq!(b.value(), 10);
+	drop(FFIDynThinBox::from(b));
+	drop(FFIDynThinBox::into_box(FFIDynThinBox::<dyn Value>::new(())));
+
+	// C frees thin boxes through the function before the vtable pointer
+	let thin = FFIDynThinBox::into_raw(FFIDynThinBox::<dyn Value>::new([1, 2, 3, 4]));
+	unsafe {
+		let free = *(thin.as_ptr() as *const unsafe extern "C" fn(*mut ())).sub(1);
+		free(thin.as_ptr());
+	}
+	assert_eq!(LIVE.load(Ordering::SeqCst), before);
+}
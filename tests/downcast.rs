@@ -0,0 +1,160 @@
+use ffi_trait::*;
+
+use std::ptr::NonNull;
+
+#[ffi_trait(type_id)]
+pub trait Shape {
+	fn area(&self) -> u32;
+}
+
+#[ffi_trait(type_id)]
+pub trait Square: Shape {
+	fn grow(&mut self, by: u32);
+}
+
+#[derive(StableTypeId, Debug, PartialEq)]
+struct Sq(u32);
+
+#[ffi_trait(type_id)]
+impl Shape for Sq {
+	fn area(&self) -> u32 { self.0 * self.0 }
+}
+
+#[ffi_trait(type_id)]
+impl Square for Sq {
+	fn grow(&mut self, by: u32) { self.0 += by }
+}
+
+#[derive(StableTypeId)]
+struct Circle;
+
+#[derive(StableTypeId)]
+struct Wrap<T>(T);
+
+#[ffi_trait(type_id)]
+impl<T: StableTypeId> Shape for Wrap<T> {
+	fn area(&self) -> u32 { 1 }
+}
+
+#[ffi_trait(type_id)]
+impl Shape for Circle {
+	fn area(&self) -> u32 { 3 }
+}
+
+// neither opts in, and neither could implement `StableTypeId`
+impl Shape for String {
+	fn area(&self) -> u32 { self.len() as u32 }
+}
+
+struct Borrowed<'a>(&'a u32);
+
+impl Shape for Borrowed<'_> {
+	fn area(&self) -> u32 { *self.0 }
+}
+
+#[test]
+fn downcast() {
+	let s = Sq(2);
+	let r: FFIDynRef<dyn Shape> = s.dyn_ref();
+	assert_eq!(r.type_id(), Some(FFITypeId::of::<Sq>()));
+	assert!(r.is::<Sq>());
+	assert!(!r.is::<Circle>());
+	assert_eq!(r.downcast_ref::<Sq>(), Some(&Sq(2)));
+	assert!(r.downcast_ref::<Circle>().is_none());
+
+	// upcasting keeps the concrete type
+	let mut s = Sq(3);
+	let mut m: FFIDynMut<dyn Square> = s.dyn_mut();
+	m.grow(1);
+	m.downcast_mut::<Sq>().unwrap().0 += 1;
+	assert!(m.upcast::<dyn Shape>().is::<Sq>());
+	assert_eq!(s, Sq(5));
+}
+
+#[test]
+fn generic() {
+	let w = Wrap(1u8);
+	let r: FFIDynRef<dyn Shape> = w.dyn_ref();
+	assert!(r.is::<Wrap<u8>>());
+	assert!(!r.is::<Wrap<u16>>());
+	assert!(r.downcast_ref::<Wrap<Wrap<u8>>>().is_none());
+}
+
+#[test]
+fn pinned() {
+	// a host and a plugin have to agree, wherever either was built
+	assert_eq!(FFITypeId::of::<Sq>(), FFITypeId::from_name("ffi-trait 0.1.0 downcast::Sq"));
+	assert_eq!(FFITypeId::of::<Sq>(), FFITypeId { high: 0xcc32e44ba85682b4, low: 0x9489933b349aca99 });
+	assert_eq!(FFITypeId::of::<u8>(), FFITypeId::from_name("ffi_trait::u8"));
+}
+
+#[test]
+fn without_type_id() {
+	let s = String::from("abc");
+	let r: FFIDynRef<dyn Shape> = s.dyn_ref();
+	assert_eq!(r.area(), 3);
+	assert_eq!(r.type_id(), None);
+	assert!(r.downcast_ref::<u8>().is_none());
+
+	let x = 4;
+	let b = Borrowed(&x);
+	let r: FFIDynRef<dyn Shape> = b.dyn_ref();
+	assert_eq!(r.area(), 4);
+}
+
+#[test]
+fn foreign_without_type_id() {
+	unsafe extern "C" fn area(_: NonNull<()>) -> u32 { 0 }
+	type ShapeBuilder = <dyn Shape as VtableBuilder>::Builder;
+	let vt = ShapeBuilder {
+		__ffi_trait__align: 1,
+		area: Some(area),
+		..Default::default()
+	};
+	let r = unsafe { FFIDynRef::<dyn Shape>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	assert_eq!(r.type_id(), None);
+	assert!(r.downcast_ref::<()>().is_none());
+}
+
+#[cfg(feature = "boxed")]
+#[test]
+fn downcast_box() {
+	let b = FFIDynBox::<dyn Shape>::new(Sq(5));
+	let b = FFIDynBox::downcast::<Circle>(b).err().unwrap();
+	assert_eq!(FFIDynBox::downcast::<Sq>(b).ok().map(|x| *x), Some(Sq(5)));
+
+	// the object in a wrapper can't be reached through it
+	let b = FFIDynBox::<dyn Shape>::new(FFIDynBox::<dyn Shape>::new(Sq(5)));
+	assert_eq!(b.type_id(), None);
+}
+
+#[cfg(feature = "boxed")]
+#[test]
+fn downcast_foreign_box() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	// a box whose memory belongs to someone else is moved out of it
+	static FREED: AtomicUsize = AtomicUsize::new(0);
+	unsafe extern "C" fn area(_: NonNull<()>) -> u32 { 0 }
+	unsafe extern "C" fn type_id() -> FFITypeId { FFITypeId::of::<Sq>() }
+	unsafe extern "C" fn dealloc(_: *mut ()) { FREED.fetch_add(1, Ordering::Relaxed); }
+	type ShapeBuilder = <dyn Shape as VtableBuilder>::Builder;
+	let vt = Box::leak(Box::new(ShapeBuilder {
+		__ffi_trait__size: 4,
+		__ffi_trait__align: 4,
+		__ffi_trait__dealloc: Some(dealloc),
+		__ffi_trait__type_id: Some(type_id),
+		area: Some(area),
+		..Default::default()
+	}));
+	let mut data = Sq(7);
+	let b = unsafe { FFIDynBox::<dyn Shape>::from_foreign(NonNull::from(&mut data).cast(), NonNull::from(vt)) }.unwrap();
+	assert_eq!(FFIDynBox::downcast::<Sq>(b).ok().map(|x| *x), Some(Sq(7)));
+	assert_eq!(FREED.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn header() {
+	let h = <dyn Shape as CHeader>::C_HEADER;
+	assert!(h.contains("\tffi_trait_type_id (*type_id)(void);"));
+}
@@ -0,0 +1,60 @@
+// `Box<dyn Trait>` goes through the vtable as an `FFIDynBox`
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+#[ffi_trait]
+pub trait Visitor {
+	fn visit(&mut self, id: u32);
+}
+
+#[ffi_trait]
+pub trait Node {
+	fn id(&self) -> u32;
+	fn accept(&self, v: &mut dyn Visitor);
+	fn same(&self, other: &dyn Node) -> bool;
+	fn child(&self) -> Box<dyn Node>;
+	fn adopt(&mut self, child: Box<dyn Node>) -> u32;
+}
+
+struct Leaf(u32);
+
+impl Node for Leaf {
+	fn id(&self) -> u32 { self.0 }
+	fn accept(&self, v: &mut dyn Visitor) { v.visit(self.0) }
+	fn same(&self, other: &dyn Node) -> bool { other.id() == self.0 }
+	fn child(&self) -> Box<dyn Node> { Box::new(Leaf(self.0 + 1)) }
+	fn adopt(&mut self, child: Box<dyn Node>) -> u32 {
+		self.0 += child.id();
+		self.0
+	}
+}
+
+#[derive(Default)]
+struct Ids(Vec<u32>);
+
+impl Visitor for Ids {
+	fn visit(&mut self, id: u32) { self.0.push(id) }
+}
+
+#[test]
+fn translated() {
+	let mut leaf = Leaf(1);
+	let mut n = FFIDynMut::<dyn Node>::from_std(&mut leaf as &mut dyn Node);
+	let mut ids = Ids::default();
+	n.accept(&mut ids);
+	assert_eq!(ids.0, [1]);
+	assert!(n.same(&Leaf(1)));
+	assert!(!n.same(&FFIDynBox::<dyn Node>::new(Leaf(2))));
+	let child = n.child();
+	assert_eq!(child.id(), 2);
+	assert_eq!(n.adopt(child), 3);
+	assert_eq!(leaf.0, 3);
+}
+
+#[test]
+fn header() {
+	let h = <dyn Node as CHeader>::C_HEADER;
+	assert!(h.contains("#include \"Visitor.h\""));
+	assert!(h.contains("\tvoid (*accept)(void *self, Visitor_ptr arg0);"));
+}
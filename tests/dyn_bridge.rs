@@ -0,0 +1,53 @@
+use ffi_trait::*;
+
+use std::rc::Rc;
+use std::cell::Cell;
+
+#[ffi_trait]
+pub trait Shape {
+	fn area(&self) -> f64;
+}
+
+struct Square(f64);
+
+impl Shape for Square {
+	fn area(&self) -> f64 { self.0 * self.0 }
+}
+
+struct Circle(f64, Rc<Cell<u32>>);
+
+impl Shape for Circle {
+	fn area(&self) -> f64 { 3.0 * self.0 * self.0 }
+}
+
+impl Drop for Circle {
+	fn drop(&mut self) { self.1.set(self.1.get() + 1) }
+}
+
+fn total(shapes: &[&dyn Shape]) -> f64 {
+	shapes.iter().map(|&x| FFIDynRef::from_std(x).area()).sum()
+}
+
+#[test]
+fn from_dyn_ref() {
+	let drops = Rc::new(Cell::new(0));
+	let c = Circle(1.0, drops.clone());
+	assert_eq!(total(&[&Square(2.0), &c]), 7.0);
+
+	let r = FFIDynRef::<dyn Shape>::from_std(&c as &dyn Shape);
+	assert_eq!(size_of_val_ref(r), std::mem::size_of::<Circle>());
+	assert_eq!(align_of_val_ref(r), std::mem::align_of::<Circle>());
+	assert_eq!(drops.get(), 0);
+}
+
+#[cfg(feature = "boxed")]
+#[test]
+fn from_dyn_box() {
+	let drops = Rc::new(Cell::new(0));
+	let b: Box<dyn Shape> = Box::new(Circle(2.0, drops.clone()));
+	let b: FFIDynBox<dyn Shape> = b.into();
+	assert_eq!(b.area(), 12.0);
+	assert_eq!(size_of_val_ref(b.as_ref()), std::mem::size_of::<Circle>());
+	drop(b);
+	assert_eq!(drops.get(), 1);
+}
@@ -0,0 +1,61 @@
+use ffi_trait::*;
+
+use std::ptr::NonNull;
+
+#[derive(FfiSafe, Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub struct Point<T> {
+	x: T,
+	y: T
+}
+
+#[derive(FfiSafe, Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum Axis {
+	X,
+	Y
+}
+
+#[ffi_trait]
+pub trait Plot {
+	fn get(&self, at: Point<i32>, axis: Axis) -> Option<NonNull<f64>>;
+	fn callback(&self) -> Option<extern "C" fn(Point<f32>) -> bool>;
+	fn sub(&mut self, plot: FFIDynMut<dyn Plot>);
+}
+
+#[ffi_trait(no_default_abi)]
+pub trait Untyped {
+	// the Rust ABI is unstable anyway, so anything goes
+	fn name(&self) -> String;
+}
+
+struct Grid(f64);
+
+extern "C" fn positive(p: Point<f32>) -> bool { p.x > 0.0 && p.y > 0.0 }
+
+impl Plot for Grid {
+	fn get(&self, at: Point<i32>, _: Axis) -> Option<NonNull<f64>> {
+		if at.x == 0 { Some(NonNull::from(&self.0)) } else { None }
+	}
+	fn callback(&self) -> Option<extern "C" fn(Point<f32>) -> bool> { Some(positive) }
+	fn sub(&mut self, plot: FFIDynMut<dyn Plot>) {
+		assert!(plot.get(Point { x: 1, y: 0 }, Axis::X).is_none());
+	}
+}
+
+impl Untyped for Grid {
+	fn name(&self) -> String { "grid".to_string() }
+}
+
+#[test]
+fn ffi_safe_types() {
+	let mut g = Grid(2.0);
+	let mut h = Grid(3.0);
+	let mut r: FFIDynMut<dyn Plot> = g.dyn_mut();
+	assert_eq!(r.get(Point { x: 0, y: 1 }, Axis::Y).map(|x| unsafe { *x.as_ref() }), Some(2.0));
+	assert!(r.callback().unwrap()(Point { x: 1.0, y: 1.0 }));
+	r.sub(h.dyn_mut());
+
+	let u: FFIDynRef<dyn Untyped> = g.dyn_ref();
+	assert_eq!(u.name(), "grid");
+}
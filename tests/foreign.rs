@@ -0,0 +1,80 @@
+use ffi_trait::*;
+
+use std::ptr::NonNull;
+
+#[ffi_trait]
+pub trait Counter {
+	extern "C" fn get(&self) -> u32;
+	extern "C" fn bump(&mut self, by: u32);
+}
+
+// what a C implementation of `Counter` would look like
+#[repr(C)]
+struct CCounter {
+	value: u32,
+	drops: *mut u32
+}
+
+unsafe extern "C" fn c_get(this: NonNull<()>) -> u32 {
+	(*(this.as_ptr() as *const CCounter)).value
+}
+
+unsafe extern "C" fn c_bump(this: NonNull<()>, by: u32) {
+	(*(this.as_ptr() as *mut CCounter)).value += by;
+}
+
+unsafe extern "C" fn c_drop(this: *mut ()) {
+	*(*(this as *mut CCounter)).drops += 1;
+}
+
+type CounterBuilder = <dyn Counter as VtableBuilder>::Builder;
+
+fn c_vtable() -> CounterBuilder {
+	CounterBuilder {
+		__ffi_trait__size: std::mem::size_of::<CCounter>(),
+		__ffi_trait__align: std::mem::align_of::<CCounter>(),
+		__ffi_trait__drop_in_place: Some(c_drop),
+		get: Some(c_get),
+		bump: Some(c_bump),
+		..Default::default()
+	}
+}
+
+#[test]
+fn foreign_mut() {
+	let mut drops = 0;
+	let mut c = CCounter { value: 1, drops: &mut drops };
+	let vt = c_vtable();
+	let mut r = unsafe { FFIDynMut::<dyn Counter>::from_foreign(NonNull::from(&mut c).cast(), NonNull::from(&vt)) }.unwrap();
+	r.bump(2);
+	assert_eq!(r.get(), 3);
+	assert_eq!(size_of_val_mut(r), std::mem::size_of::<CCounter>());
+	assert_eq!(c.value, 3);
+}
+
+#[cfg(feature = "boxed")]
+#[test]
+fn foreign_box() {
+	let mut drops = 0;
+	// the default `dealloc` is null, so the box only drops the data
+	let mut c = CCounter { value: 5, drops: &mut drops };
+	let vt: &'static _ = Box::leak(Box::new(c_vtable()));
+	let b = unsafe { FFIDynBox::<dyn Counter>::from_foreign(NonNull::from(&mut c).cast(), NonNull::from(vt)) }.unwrap();
+	assert_eq!(b.get(), 5);
+	drop(b);
+	assert_eq!(drops, 1);
+}
+
+#[test]
+fn rejected() {
+	let mut vt = c_vtable();
+	vt.bump = None;
+	assert_eq!(<dyn Counter as VtableBuilder>::build(&vt).err(), Some(VtableError::NullMethod("bump")));
+
+	let mut vt = c_vtable();
+	vt.__ffi_trait__align = 3;
+	assert_eq!(<dyn Counter as VtableBuilder>::check(&vt).err(), Some(VtableError::BadAlign(3)));
+
+	let vt = CounterBuilder::default();
+	assert!(<dyn Counter as VtableBuilder>::validate(&vt).is_err());
+}
@@ -0,0 +1,65 @@
+use ffi_trait::*;
+
+#[repr(C)]
+#[derive(Copy, Clone, FfiSafe)]
+pub struct Event {
+	pub kind: u32,
+	pub value: u32
+}
+
+#[ffi_trait(instantiate(Sink<u32>, Sink<Event>))]
+pub trait Sink<T> {
+	fn push(&mut self, x: T) -> u32;
+}
+
+#[ffi_trait]
+pub trait Source {
+	fn drain(&self, to: FFIDynMut<dyn Sink<u32>>) -> u32;
+}
+
+#[derive(Default)]
+struct Total(u32);
+
+impl Sink<u32> for Total {
+	fn push(&mut self, x: u32) -> u32 {
+		self.0 += x;
+		self.0
+	}
+}
+
+impl Sink<Event> for Total {
+	fn push(&mut self, x: Event) -> u32 {
+		self.0 += x.kind * x.value;
+		self.0
+	}
+}
+
+struct Numbers;
+
+impl Source for Numbers {
+	fn drain(&self, mut to: FFIDynMut<dyn Sink<u32>>) -> u32 {
+		to.push(1);
+		to.push(2)
+	}
+}
+
+#[test]
+fn instantiations() {
+	let mut t = Total::default();
+	assert_eq!(FFIDynMut::<dyn Sink<u32>>::from_std(&mut t as &mut dyn Sink<u32>).push(2), 2);
+	assert_eq!(FFIDynMut::<dyn Sink<Event>>::from_std(&mut t as &mut dyn Sink<Event>).push(Event { kind: 2, value: 3 }), 8);
+	let sink = FFIDynMut::<dyn Sink<u32>>::from_std(&mut t as &mut dyn Sink<u32>);
+	assert_eq!(FFIDynRef::<dyn Source>::from_std(&Numbers as &dyn Source).drain(sink), 11);
+	assert_ne!(<dyn Sink<u32> as Interface>::ID, <dyn Sink<Event> as Interface>::ID);
+}
+
+#[test]
+fn header() {
+	let h = <dyn Sink<u32> as CHeader>::C_HEADER;
+	assert!(h.contains("struct Sink_u32_vtable {"));
+	assert!(h.contains("\tuint32_t (*push)(void *self, uint32_t arg0);"));
+	assert!(<dyn Sink<Event> as CHeader>::C_HEADER.contains("\tuint32_t (*push)(void *self, Event arg0);"));
+	let h = <dyn Source as CHeader>::C_HEADER;
+	assert!(h.contains("#include \"Sink_u32.h\""));
+	assert!(h.contains("\tuint32_t (*drain)(void *self, Sink_u32_ptr arg0);"));
+}
@@ -0,0 +1,92 @@
+// `String` and `Vec` go through the vtable as `FFIString` and `FFIVec`
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+mod common;
+use common::CDir;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[ffi_trait]
+pub trait Buffer {
+	fn name(&self) -> &str;
+	fn head(&self, n: usize) -> &[u8];
+	fn write(&mut self, buf: &[u8]) -> usize;
+	fn fill(&self, out: &mut [u8]);
+	fn contents(&self) -> Vec<u8>;
+	fn describe(&self, prefix: &str) -> String;
+	fn rename(&mut self, name: String);
+}
+
+struct Mem {
+	name: String,
+	data: Vec<u8>
+}
+
+impl Buffer for Mem {
+	fn name(&self) -> &str { &self.name }
+	fn head(&self, n: usize) -> &[u8] { &self.data[..n] }
+	fn write(&mut self, buf: &[u8]) -> usize {
+		self.data.extend_from_slice(buf);
+		buf.len()
+	}
+	fn fill(&self, out: &mut [u8]) { out.copy_from_slice(&self.data[..out.len()]) }
+	fn contents(&self) -> Vec<u8> { self.data.clone() }
+	fn describe(&self, prefix: &str) -> String { format!("{}{} ({} bytes)", prefix, self.name, self.data.len()) }
+	fn rename(&mut self, name: String) { self.name = name }
+}
+
+#[test]
+fn through_vtable() {
+	let mut m = Mem { name: "mem".to_string(), data: Vec::new() };
+	let mut b = FFIDynMut::<dyn Buffer>::from_std(&mut m as &mut dyn Buffer);
+	assert_eq!(b.write(b"hello"), 5);
+	assert_eq!(b.name(), "mem");
+	assert_eq!(b.head(2), b"he");
+	let mut out = [0; 3];
+	b.fill(&mut out);
+	assert_eq!(&out, b"hel");
+	assert_eq!(b.contents(), b"hello");
+	b.rename("buf".to_string());
+	assert_eq!(b.describe("> "), "> buf (5 bytes)");
+}
+
+static FREED: AtomicUsize = AtomicUsize::new(0);
+
+// frees like another allocator would, without dropping the elements
+unsafe extern "C" fn free_leaked(_: *mut u8, len: usize, _: usize) {
+	FREED.fetch_add(1 + len, Ordering::SeqCst);
+}
+
+#[test]
+fn foreign_vec() {
+	let data = Box::leak(Box::new(*b"abc"));
+	let v = unsafe { FFIVec::from_raw_parts(data.as_mut_ptr(), 3, 3, free_leaked) };
+	assert_eq!(&*v, b"abc");
+	// the elements are moved to a buffer of our own, and the foreign one is freed
+	assert_eq!(v.into_vec(), b"abc");
+	assert_eq!(FREED.load(Ordering::SeqCst), 1);
+	assert_eq!(FFIString::from_std("abc".to_string()).into_string(), "abc");
+	assert_eq!(unsafe { FFIStr::from_raw_parts(std::ptr::null(), 0) }.as_str(), "");
+}
+
+#[test]
+fn header() {
+	let h = <dyn Buffer as CHeader>::C_HEADER;
+	assert!(h.contains("\tffi_trait_str (*name)(void *self);"));
+	assert!(h.contains("\tsize_t (*write)(void *self, ffi_trait_slice arg0);"));
+	assert!(h.contains("\tvoid (*fill)(void *self, ffi_trait_slice_mut arg0);"));
+	assert!(h.contains("\tffi_trait_vec (*contents)(void *self);"));
+	assert!(h.contains("\tffi_trait_string (*describe)(void *self, ffi_trait_str arg0);"));
+}
+
+#[test]
+fn c_header_compiles() {
+	let dir = CDir::new("marshal");
+	dir.write("Buffer.h", <dyn Buffer as CHeader>::C_HEADER);
+	dir.check(concat!(
+		"#include \"Buffer.h\"\n",
+		"size_t f(Buffer_ptr x) { ffi_trait_vec v = Buffer_contents(x); size_t n = v.len; v.drop(v.ptr, v.len, v.cap); return n + Buffer_name(x).len; }\n"
+	));
+}
@@ -0,0 +1,124 @@
+use ffi_trait::*;
+
+mod common;
+use common::CDir;
+
+use std::cell::RefCell;
+use std::ptr::NonNull;
+
+#[derive(FfiSafe, Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+pub struct Data {
+	id: u32,
+	weight: f64
+}
+
+#[derive(FfiSafe, Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+pub enum MyError {
+	Missing,
+	Locked
+}
+
+#[ffi_trait]
+pub trait Store {
+	fn count(&self, key: u32) -> Option<u32>;
+	fn load(&self, key: u32) -> Result<Data, MyError>;
+	fn store(&self, key: u32, data: Option<Data>) -> Result<(), MyError>;
+	fn label(&self, key: u32) -> Option<&str>;
+	// already a nullable pointer, so passed as it is
+	fn fallback(&self) -> Option<NonNull<Data>>;
+}
+
+struct Mem {
+	items: RefCell<Vec<(u32, Data)>>,
+	fallback: Option<Data>,
+	locked: bool
+}
+
+impl Store for Mem {
+	fn count(&self, key: u32) -> Option<u32> {
+		if key == 0 { None } else { Some(self.items.borrow().iter().filter(|x| x.0 == key).count() as u32) }
+	}
+	fn load(&self, key: u32) -> Result<Data, MyError> {
+		self.items.borrow().iter().find(|x| x.0 == key).map(|x| x.1).ok_or(MyError::Missing)
+	}
+	fn store(&self, key: u32, data: Option<Data>) -> Result<(), MyError> {
+		if self.locked {
+			return Err(MyError::Locked);
+		}
+		let mut items = self.items.borrow_mut();
+		items.retain(|x| x.0 != key);
+		items.extend(data.map(|x| (key, x)));
+		Ok(())
+	}
+	fn label(&self, key: u32) -> Option<&str> {
+		if self.items.borrow().iter().any(|x| x.0 == key) { Some("stored") } else { None }
+	}
+	fn fallback(&self) -> Option<NonNull<Data>> {
+		self.fallback.as_ref().map(NonNull::from)
+	}
+}
+
+#[test]
+fn through_vtable() {
+	let data = Data { id: 7, weight: 1.5 };
+	let mut m = Mem { items: RefCell::new(Vec::new()), fallback: Some(data), locked: false };
+	let s = FFIDynRef::<dyn Store>::from_std(&m as &dyn Store);
+	assert_eq!(s.count(0), None);
+	assert_eq!(s.count(1), Some(0));
+	assert_eq!(s.load(1), Err(MyError::Missing));
+	assert_eq!(s.store(1, Some(data)), Ok(()));
+	assert_eq!(s.load(1), Ok(data));
+	assert_eq!(s.label(1), Some("stored"));
+	assert_eq!(s.label(2), None);
+	assert_eq!(s.fallback().map(|x| unsafe { *x.as_ref() }), Some(data));
+	assert_eq!(s.store(1, None), Ok(()));
+	assert_eq!(s.count(1), Some(0));
+	m.locked = true;
+	m.fallback = None;
+	let s = FFIDynMut::<dyn Store>::from_std(&mut m as &mut dyn Store);
+	assert_eq!(s.store(1, Some(data)), Err(MyError::Locked));
+	assert!(s.fallback().is_none());
+}
+
+#[test]
+fn conversions() {
+	let x: FFIOption<u32> = Some(3).into();
+	assert!(x.is_some());
+	assert_eq!(x.clone().into_option(), Some(3));
+	assert_eq!(format!("{:?}", FFIOption::<u32>::none()), "None");
+	let x: FFIResult<u32, MyError> = Err(MyError::Locked).into();
+	assert!(!x.is_ok());
+	assert_eq!(Result::from(x), Err(MyError::Locked));
+	// the value is dropped with the wrapper
+	let rc = std::rc::Rc::new(());
+	drop(FFIOption::some(rc.clone()));
+	drop(FFIResult::<_, ()>::ok(rc.clone()));
+	assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+}
+
+#[test]
+fn header() {
+	let h = <dyn Store as CHeader>::C_HEADER;
+	assert!(h.contains("\tffi_trait_option_uint32_t (*count)(void *self, uint32_t arg0);"));
+	assert!(h.contains("\tffi_trait_result_Data_MyError (*load)(void *self, uint32_t arg0);"));
+	assert!(h.contains("\tffi_trait_result_void_MyError (*store)(void *self, uint32_t arg0, ffi_trait_option_Data arg1);"));
+	assert!(h.contains("\tffi_trait_option_ffi_trait_str (*label)(void *self, uint32_t arg0);"));
+	assert!(h.contains("\tData *(*fallback)(void *self);"));
+	assert!(h.contains("typedef struct ffi_trait_result_void_MyError {\n\tbool is_ok;\n\tunion {\n\t\tMyError err;\n\t} value;\n}"));
+}
+
+#[test]
+fn c_header_compiles() {
+	let dir = CDir::new("option");
+	dir.write("Store.h", <dyn Store as CHeader>::C_HEADER);
+	dir.check(concat!(
+		"#include <stdint.h>\n",
+		"typedef struct Data { uint32_t id; double weight; } Data;\n",
+		"typedef uint8_t MyError;\n",
+		"#include \"Store.h\"\n",
+		"uint32_t f(Store_ptr x) { ffi_trait_option_uint32_t n = Store_count(x, 1); ffi_trait_result_Data_MyError d = Store_load(x, 1);",
+		" return (n.is_some ? n.value : 0) + (d.is_ok ? d.value.ok.id : d.value.err); }\n"
+	));
+}
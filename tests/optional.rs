@@ -0,0 +1,74 @@
+use ffi_trait::*;
+
+use std::ptr::NonNull;
+
+#[ffi_trait]
+pub trait Plugin {
+	fn name(&self) -> u32;
+	#[ffi_trait(optional)]
+	fn priority(&self, base: u32) -> u32 {
+		base
+	}
+	#[ffi_trait(optional)]
+	fn reload(&mut self);
+}
+
+struct Native(u32);
+
+impl Plugin for Native {
+	fn name(&self) -> u32 { 1 }
+	fn reload(&mut self) { self.0 += 1 }
+}
+
+type PluginBuilder = <dyn Plugin as VtableBuilder>::Builder;
+
+unsafe extern "C" fn name(_: NonNull<()>) -> u32 { 2 }
+unsafe extern "C" fn priority(_: NonNull<()>, base: u32) -> u32 { base + 10 }
+
+fn c_vtable() -> PluginBuilder {
+	PluginBuilder {
+		__ffi_trait__align: 1,
+		name: Some(name),
+		..Default::default()
+	}
+}
+
+#[test]
+fn native() {
+	let mut n = Native(0);
+	let mut m: FFIDynMut<dyn Plugin> = n.dyn_mut();
+	assert_eq!(m.priority(3), 3);
+	assert_eq!(m.try_reload(), Some(()));
+	m.reload();
+	assert_eq!(n.0, 2);
+}
+
+#[test]
+fn null_slots() {
+	let vt = c_vtable();
+	let mut m = unsafe { FFIDynMut::<dyn Plugin>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	assert_eq!(m.name(), 2);
+	// falls back to the default body
+	assert_eq!(m.try_priority(3), None);
+	assert_eq!(m.priority(3), 3);
+	assert_eq!(m.try_reload(), None);
+
+	let vt = PluginBuilder { priority: Some(priority), ..c_vtable() };
+	let m = unsafe { FFIDynMut::<dyn Plugin>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	assert_eq!(m.priority(3), 13);
+}
+
+#[test]
+#[should_panic(expected = "ffi_trait method `reload` is missing from the vtable")]
+fn missing_without_default() {
+	let vt = c_vtable();
+	let mut m = unsafe { FFIDynMut::<dyn Plugin>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	m.reload();
+}
+
+#[test]
+fn header() {
+	let h = <dyn Plugin as CHeader>::C_HEADER;
+	assert!(h.contains("\tvoid (*reload)(void *self); /* optional, may be null */"));
+	assert!(h.contains("static inline bool Plugin_has_priority(Plugin_ptr self) {"));
+}
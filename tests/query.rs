@@ -0,0 +1,100 @@
+use ffi_trait::*;
+
+use std::ptr::NonNull;
+
+#[ffi_trait(query_interface)]
+pub trait Reader {
+	fn read(&mut self) -> u8;
+}
+
+#[ffi_trait]
+pub trait Seekable {
+	fn seek(&mut self, pos: u8);
+}
+
+#[ffi_trait]
+pub trait Named {
+	fn name(&self) -> u32;
+}
+
+struct Cursor(u8);
+
+#[ffi_trait(provides(Seekable))]
+impl Reader for Cursor {
+	fn read(&mut self) -> u8 {
+		self.0 += 1;
+		self.0
+	}
+}
+
+impl Seekable for Cursor {
+	fn seek(&mut self, pos: u8) { self.0 = pos }
+}
+
+impl Named for Cursor {
+	fn name(&self) -> u32 { 1 }
+}
+
+struct Empty;
+
+#[ffi_trait(provides())]
+impl Reader for Empty {
+	fn read(&mut self) -> u8 { 0 }
+}
+
+#[test]
+fn query() {
+	let mut c = Cursor(0);
+	let r: FFIDynMut<dyn Reader> = c.dyn_mut();
+	// `Cursor` implements `Named` but doesn't say so
+	assert!(r.query::<dyn Named>().is_none());
+	assert!(r.query::<dyn Reader>().is_some());
+	let mut s = r.query_mut::<dyn Seekable>().unwrap();
+	s.seek(5);
+	assert_eq!(c.read(), 6);
+
+	let mut e = Empty;
+	let r: FFIDynMut<dyn Reader> = e.dyn_mut();
+	assert!(r.query::<dyn Seekable>().is_none());
+}
+
+#[cfg(feature = "boxed")]
+#[test]
+fn wrappers_forward() {
+	// a box implements `Reader` through its own vtable, which asks the one inside
+	let b = FFIDynBox::<dyn Reader>::new(Cursor(1));
+	let mut outer = FFIDynBox::<dyn Reader>::new(b);
+	assert_eq!(outer.read(), 2);
+	let outer = outer.as_mut();
+	assert!(outer.query::<dyn Seekable>().is_some());
+}
+
+// what a C implementation which provides nothing else would look like
+unsafe extern "C" fn read(_: NonNull<()>) -> u8 { 7 }
+unsafe extern "C" fn query_nothing(_: NonNull<()>, _: FFITypeId) -> FFIDynPtr<dyn FFIUnknown> { FFIDynPtr::null() }
+
+#[test]
+fn foreign() {
+	type ReaderBuilder = <dyn Reader as VtableBuilder>::Builder;
+	let vt = ReaderBuilder {
+		__ffi_trait__align: 1,
+		read: Some(read),
+		..Default::default()
+	};
+	let r = unsafe { FFIDynMut::<dyn Reader>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	assert!(r.query::<dyn Seekable>().is_none());
+
+	let vt = ReaderBuilder { __ffi_trait__query_interface: Some(query_nothing), ..vt };
+	let r = unsafe { FFIDynMut::<dyn Reader>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	assert!(r.query::<dyn Seekable>().is_none());
+}
+
+#[test]
+fn interface_ids() {
+	assert_ne!(<dyn Reader as Interface>::ID, <dyn Seekable as Interface>::ID);
+	let id = <dyn Seekable as Interface>::ID;
+	let h = <dyn Seekable as CHeader>::C_HEADER;
+	assert!(h.contains(&format!("{{ UINT64_C(0x{:016x}), UINT64_C(0x{:016x}) }}", id.high, id.low)));
+	let h = <dyn Reader as CHeader>::C_HEADER;
+	assert!(h.contains("\tffi_trait_unknown_ptr (*query_interface)(void *self, ffi_trait_type_id id);"));
+}
@@ -0,0 +1,117 @@
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[ffi_trait]
+pub trait Named {
+	fn id(&self) -> u32;
+}
+
+#[ffi_trait]
+pub trait Plugin: Named {
+	fn version(&self) -> u32;
+}
+
+struct Counted<'a>(u32, &'a AtomicU32);
+
+impl Named for Counted<'_> {
+	fn id(&self) -> u32 { self.0 }
+}
+
+impl Plugin for Counted<'_> {
+	fn version(&self) -> u32 { 2 }
+}
+
+impl Drop for Counted<'_> {
+	fn drop(&mut self) { self.1.fetch_add(1, Ordering::SeqCst); }
+}
+
+#[test]
+fn arc() {
+	static DROPS: AtomicU32 = AtomicU32::new(0);
+	let a: FFIDynArc<dyn Plugin> = Arc::new(Counted(7, &DROPS)).into();
+	let b = a.clone();
+	assert!(FFIDynArc::ptr_eq(&a, &b));
+	assert_eq!(b.version(), 2);
+	assert_eq!(a.as_ref().id(), 7);
+	drop(a);
+	assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+
+	let n = FFIDynArc::<dyn Plugin>::upcast::<dyn Named>(b);
+	assert_eq!(n.id(), 7);
+	drop(n);
+	assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+}
+
+struct Local(Rc<Cell<u32>>);
+
+impl Named for Local {
+	fn id(&self) -> u32 { 1 }
+}
+
+impl Drop for Local {
+	fn drop(&mut self) { self.0.set(self.0.get() + 1) }
+}
+
+#[test]
+fn rc_as_foreign_code_would() {
+	let drops = Rc::new(Cell::new(0));
+	let r = FFIDynRc::<dyn Named>::new(Local(drops.clone()));
+
+	// what a C plugin holding the handle would do
+	let (ptr, rc) = FFIDynRc::into_raw_parts(r);
+	unsafe {
+		(rc.retain)(ptr.data.as_ptr());
+		(rc.release)(ptr.data.as_ptr());
+		(rc.retain)(ptr.data.as_ptr());
+	}
+
+	let a = unsafe { FFIDynRc::from_raw_parts(ptr, rc) };
+	let b = unsafe { FFIDynRc::from_raw_parts(ptr, rc) };
+	assert_eq!(a.id(), 1);
+	drop(a);
+	assert_eq!(drops.get(), 0);
+	drop(b);
+	assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn weak() {
+	static DROPS: AtomicU32 = AtomicU32::new(0);
+	let a = FFIDynArc::<dyn Plugin>::new(Counted(3, &DROPS));
+	let w = FFIDynArc::downgrade(&a);
+	let w2 = w.clone();
+	assert!(FFIDynArc::ptr_eq(&w.upgrade().unwrap(), &a));
+	drop(w);
+
+	let n = FFIDynWeak::<dyn Plugin>::upcast::<dyn Named>(w2);
+	assert_eq!(n.upgrade().unwrap().id(), 3);
+	drop(a);
+	assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+	assert!(n.upgrade().is_none());
+}
+
+#[test]
+fn rc_weak_as_foreign_code_would() {
+	let drops = Rc::new(Cell::new(0));
+	let r = FFIDynRc::<dyn Named>::new(Local(drops.clone()));
+
+	let (ptr, rc) = FFIDynRc::into_raw_parts(r);
+	unsafe {
+		(rc.downgrade)(ptr.data.as_ptr());
+		(rc.retain_weak)(ptr.data.as_ptr());
+		(rc.release_weak)(ptr.data.as_ptr());
+		assert!((rc.upgrade)(ptr.data.as_ptr()));
+		(rc.release)(ptr.data.as_ptr());
+		(rc.release)(ptr.data.as_ptr());
+	}
+	assert_eq!(drops.get(), 1);
+
+	let w = unsafe { FFIDynRcWeak::from_raw_parts(ptr, rc) };
+	assert!(w.upgrade().is_none());
+}
@@ -0,0 +1,104 @@
+// the wrappers of `dyn Trait + Send` and of `Send` traits move between threads
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+
+#[ffi_trait]
+pub trait Plugin: Send + Sync {
+	fn run(&self, x: u32) -> u32;
+}
+
+#[ffi_trait]
+pub trait Named {
+	fn name(&self) -> u32;
+}
+
+#[ffi_trait]
+pub trait Counter: Named {
+	fn add(&mut self, n: u32) -> u32;
+}
+
+#[ffi_trait(instantiate(Sink<u32>))]
+pub trait Sink<T>: Send {
+	fn push(&mut self, x: T);
+}
+
+struct Doubler(AtomicU32);
+
+impl Plugin for Doubler {
+	fn run(&self, x: u32) -> u32 {
+		self.0.fetch_add(1, Ordering::SeqCst);
+		x * 2
+	}
+}
+
+struct Total(u32);
+
+impl Named for Total {
+	fn name(&self) -> u32 { 7 }
+}
+
+impl Counter for Total {
+	fn add(&mut self, n: u32) -> u32 {
+		self.0 += n;
+		self.0
+	}
+}
+
+impl Sink<u32> for Total {
+	fn push(&mut self, x: u32) { self.0 += x }
+}
+
+#[test]
+fn auto_supertraits() {
+	let p = FFIDynBox::<dyn Plugin>::new(Doubler(AtomicU32::new(0)));
+	let r = p.as_ref();
+	thread::scope(|s| {
+		for i in 0..4 {
+			s.spawn(move || assert_eq!(r.run(i), i * 2));
+		}
+	});
+	let p = thread::spawn(move || {
+		assert_eq!(p.run(5), 10);
+		p
+	}).join().unwrap();
+	let b: Box<dyn Plugin> = p.into();
+	assert_eq!(b.run(1), 2);
+
+	let mut s = FFIDynBox::<dyn Sink<u32>>::new(Total(0));
+	let s = thread::spawn(move || {
+		s.push(3);
+		s
+	}).join().unwrap();
+	let mut t = Total(0);
+	Sink::push(&mut FFIDynMut::<dyn Sink<u32>>::from_std(&mut t as &mut dyn Sink<u32>), 1);
+	assert_eq!(t.0, 1);
+	drop(s);
+}
+
+#[test]
+fn send_objects() {
+	let mut c = FFIDynBox::<dyn Counter + Send>::new(Total(1));
+	let c = thread::spawn(move || {
+		assert_eq!(c.add(2), 3);
+		c
+	}).join().unwrap();
+	assert_eq!(c.name(), 7);
+	// the bound can be dropped, also when upcasting to a supertrait
+	let named = FFIDynBox::upcast::<dyn Named + Send>(c);
+	let named = thread::spawn(move || named).join().unwrap();
+	assert_eq!(FFIDynBox::upcast::<dyn Named>(named).name(), 7);
+
+	let mut t = Total(0);
+	let m = FFIDynMut::<dyn Counter + Send + Sync>::from_std(&mut t as &mut (dyn Counter + Send + Sync));
+	thread::scope(|s| {
+		s.spawn(move || {
+			let mut m = m.upcast::<dyn Counter + Send>();
+			m.add(4);
+		});
+	});
+	assert_eq!(t.0, 4);
+}
@@ -0,0 +1,69 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+pub trait Named {
+	fn name(&self) -> u32;
+}
+
+#[ffi_trait]
+pub trait Animal: Named {
+	fn legs(&self) -> u32;
+}
+
+// every `ffi_trait` ancestor has to be listed, not just the direct parent, see
+// tests/ui/missing_ancestor.rs
+#[ffi_trait]
+pub trait Pet: Animal + Named {
+	fn pet(&mut self) -> u32;
+}
+
+struct Dog(u32);
+
+impl Named for Dog {
+	fn name(&self) -> u32 { 7 }
+}
+
+impl Animal for Dog {
+	fn legs(&self) -> u32 { 4 }
+}
+
+impl Pet for Dog {
+	fn pet(&mut self) -> u32 { self.0 += 1; self.0 }
+}
+
+fn legs_and_name<T: Animal + ?Sized>(x: &T) -> u32 {
+	x.legs() * 10 + x.name()
+}
+
+#[test]
+fn supertrait_methods() {
+	let mut dog = Dog(0);
+	let r = FFIDynRef::<dyn Animal>::from_std(&dog as &dyn Animal);
+	assert_eq!(legs_and_name(&r), 47);
+
+	let mut m = FFIDynMut::<dyn Pet>::from_std(&mut dog as &mut dyn Pet);
+	assert_eq!(m.pet(), 1);
+	assert_eq!(legs_and_name(&m), 47);
+	assert_eq!(m.name(), 7);
+}
+
+#[test]
+fn upcast() {
+	let mut dog = Dog(0);
+	let m = FFIDynMut::<dyn Pet>::from_std(&mut dog as &mut dyn Pet);
+	let a: FFIDynMut<dyn Animal> = m.upcast();
+	assert_eq!(a.legs(), 4);
+	let n: FFIDynRef<dyn Named> = unsafe { a.to_ptr().to_ref() }.upcast();
+	assert_eq!(n.name(), 7);
+	assert_eq!(size_of_val_ref(n), std::mem::size_of::<Dog>());
+}
+
+#[cfg(feature = "boxed")]
+#[test]
+fn upcast_box() {
+	let b: FFIDynBox<dyn Pet> = FFIDynBox::new(Dog(3));
+	let b: FFIDynBox<dyn Animal> = FFIDynBox::upcast(b);
+	assert_eq!(b.legs(), 4);
+	let b: FFIDynBox<dyn Named> = FFIDynBox::upcast(b);
+	assert_eq!(b.name(), 7);
+}
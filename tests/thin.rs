@@ -0,0 +1,130 @@
+#![cfg(feature = "boxed")]
+
+use ffi_trait::*;
+
+use std::cell::Cell;
+use std::os::raw::c_void;
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+#[ffi_trait]
+pub trait Counter {
+	fn get(&self) -> u32;
+	fn add(&self, x: u32);
+}
+
+// aligned past the vtable pointer, so that the data is padded
+#[repr(align(32))]
+struct Tracked(Cell<u32>, Rc<Cell<u32>>);
+
+impl Counter for Tracked {
+	fn get(&self) -> u32 { self.0.get() }
+	fn add(&self, x: u32) { self.0.set(self.0.get() + x) }
+}
+
+impl Drop for Tracked {
+	fn drop(&mut self) { self.1.set(self.1.get() + 1) }
+}
+
+impl Counter for () {
+	fn get(&self) -> u32 { 0 }
+	fn add(&self, _: u32) {}
+}
+
+// what a C API would call with its `void *` user data
+extern "C" fn callback(user: *mut c_void) -> u32 {
+	let r = unsafe { FFIDynThinRef::<dyn Counter>::from_raw(NonNull::new(user as *mut ()).unwrap()) };
+	r.get()
+}
+
+#[test]
+fn call_and_drop() {
+	let drops = Rc::new(Cell::new(0));
+	let b = FFIDynThinBox::<dyn Counter>::new(Tracked(Cell::new(1), drops.clone()));
+	b.add(2);
+	assert_eq!(b.get(), 3);
+	assert_eq!(b.as_ref().get(), 3);
+	b.as_thin_ref().add(1);
+	let thin = b.as_thin_ref().as_raw().as_ptr();
+	assert_eq!(b.data().as_ptr() as usize - thin as usize, 32);
+	assert_eq!(callback(thin as *mut c_void), 4);
+	drop(b);
+	assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn box_roundtrip() {
+	let drops = Rc::new(Cell::new(0));
+	let b = FFIDynBox::<dyn Counter>::new(Tracked(Cell::new(1), drops.clone()));
+	let b = FFIDynThinBox::from(b);
+	b.add(1);
+	let raw = FFIDynThinBox::into_raw(b);
+	let b = unsafe { FFIDynThinBox::<dyn Counter>::from_raw(raw) };
+	let b = FFIDynBox::from(b);
+	assert_eq!(b.get(), 2);
+	assert_eq!(drops.get(), 0);
+	drop(b);
+	assert_eq!(drops.get(), 1);
+
+	// zero-sized data isn't allocated by the box
+	let b = FFIDynThinBox::into_box(FFIDynThinBox::<dyn Counter>::new(()));
+	assert_eq!(b.get(), 0);
+	let b = FFIDynThinBox::from(b);
+	assert_eq!(b.as_thin_ref().to_ref().get(), 0);
+}
+
+#[test]
+fn foreign_box_roundtrip() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	// the box's memory belongs to C, so it has to come back in the same allocation
+	static FREED: AtomicUsize = AtomicUsize::new(0);
+	unsafe extern "C" fn get(this: NonNull<()>) -> u32 { *this.cast::<u32>().as_ref() }
+	unsafe extern "C" fn add(this: NonNull<()>, x: u32) { *this.cast::<u32>().as_mut() += x }
+	unsafe extern "C" fn dealloc(_: *mut ()) { FREED.fetch_add(1, Ordering::Relaxed); }
+	type CounterBuilder = <dyn Counter as VtableBuilder>::Builder;
+	let vt = Box::leak(Box::new(CounterBuilder {
+		__ffi_trait__size: 4,
+		__ffi_trait__align: 4,
+		__ffi_trait__dealloc: Some(dealloc),
+		get: Some(get),
+		add: Some(add),
+		..Default::default()
+	}));
+	let data = NonNull::from(Box::leak(Box::new(1u32))).cast::<()>();
+	let b = unsafe { FFIDynBox::<dyn Counter>::from_foreign(data, NonNull::from(&*vt)) }.unwrap();
+	let thin = FFIDynThinBox::from(b);
+	thin.add(2);
+	assert_eq!(FREED.load(Ordering::Relaxed), 0);
+	let b = FFIDynBox::from(thin);
+	assert_eq!(b.data(), data);
+	assert_eq!(b.get(), 3);
+	drop(b);
+	assert_eq!(FREED.load(Ordering::Relaxed), 1);
+
+	// a thin box dropped as such frees it too
+	drop(FFIDynThinBox::from(unsafe { FFIDynBox::<dyn Counter>::from_foreign(data, NonNull::from(&*vt)) }.unwrap()));
+	assert_eq!(FREED.load(Ordering::Relaxed), 2);
+	drop(unsafe { Box::from_raw(data.cast::<u32>().as_ptr()) });
+}
+
+// what `Counter_thin_drop` does
+#[test]
+fn foreign_drop() {
+	let drops = Rc::new(Cell::new(0));
+	let thin = FFIDynThinBox::into_raw(FFIDynThinBox::<dyn Counter>::new(Tracked(Cell::new(1), drops.clone())));
+	unsafe {
+		let r = FFIDynThinRef::<dyn Counter>::from_raw(thin).to_ref();
+		drop_in_place_ref(r);
+		let free = *(thin.as_ptr() as *const unsafe extern "C" fn(*mut ())).sub(1);
+		free(thin.as_ptr());
+	}
+	assert_eq!(drops.get(), 1);
+}
+
+#[test]
+fn header() {
+	let h = <dyn Counter as CHeader>::C_HEADER;
+	assert!(h.contains("static inline Counter_ptr Counter_thin_get(void *thin) {"));
+	assert!(h.contains("static inline void Counter_thin_drop(void *thin) {"));
+}
@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait(default_abi = 1)]
+trait Foo {
+	fn a(&self);
+}
+
+fn main() {}
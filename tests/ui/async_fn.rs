@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	async fn a(&self);
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	#[ffi_trait(c_header)]
+	fn a(&self);
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait(on_panic = "ignore")]
+trait Foo {
+	fn a(&self);
+}
+
+fn main() {}
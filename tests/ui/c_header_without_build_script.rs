@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait(c_header)]
+trait Foo {
+	fn a(&self);
+}
+
+fn main() {}
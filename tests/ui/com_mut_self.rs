@@ -0,0 +1,9 @@
+use ffi_trait::*;
+
+#[ffi_trait(layout = "com")]
+trait Foo {
+	fn a(&self);
+	fn b(&mut self);
+}
+
+fn main() {}
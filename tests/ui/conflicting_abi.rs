@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait(default_abi = "C", no_default_abi)]
+trait Foo {
+	fn a(&self);
+}
+
+fn main() {}
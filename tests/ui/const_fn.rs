@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	const fn a(&self);
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use ffi_trait::*;
+
+#[derive(StableTypeId)]
+struct Name<'a>(&'a str);
+
+fn main() {}
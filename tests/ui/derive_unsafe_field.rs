@@ -0,0 +1,10 @@
+use ffi_trait::*;
+
+#[derive(FfiSafe)]
+#[repr(C)]
+struct Name {
+	len: usize,
+	name: String
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use ffi_trait::*;
+
+#[derive(FfiSafe)]
+struct Point {
+	x: f32,
+	y: f32
+}
+
+fn main() {}
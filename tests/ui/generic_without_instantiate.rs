@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Sink<T> {
+	fn push(&self, x: T);
+}
+
+fn main() {}
@@ -0,0 +1,18 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Named {
+	fn name(&self) -> u32;
+}
+
+#[ffi_trait]
+trait Animal: Named {
+	fn legs(&self) -> u32;
+}
+
+#[ffi_trait]
+trait Pet: Animal {
+	fn pet(&mut self);
+}
+
+fn main() {}
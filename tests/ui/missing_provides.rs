@@ -0,0 +1,14 @@
+use ffi_trait::*;
+
+#[ffi_trait(query_interface)]
+trait Reader {
+	fn read(&mut self) -> u8;
+}
+
+struct Cursor;
+
+impl Reader for Cursor {
+	fn read(&mut self) -> u8 { 0 }
+}
+
+fn main() {}
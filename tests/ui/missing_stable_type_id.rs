@@ -0,0 +1,15 @@
+use ffi_trait::*;
+
+#[ffi_trait(type_id)]
+trait Shape {
+	fn area(&self) -> u32;
+}
+
+struct Circle;
+
+#[ffi_trait(type_id)]
+impl Shape for Circle {
+	fn area(&self) -> u32 { 3 }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	const fn a(&self);
+	fn b(x: u8);
+}
+
+fn main() {}
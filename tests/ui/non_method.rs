@@ -0,0 +1,9 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	foo!();
+	fn a(&self);
+}
+
+fn main() {}
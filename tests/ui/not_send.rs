@@ -0,0 +1,17 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	fn a(&self);
+}
+
+impl Foo for std::rc::Rc<u32> {
+	fn a(&self) {}
+}
+
+fn send<T: Send>(_: T) {}
+
+fn main() {
+	send(FFIDynBox::<dyn Foo>::new(std::rc::Rc::new(0)));
+	let _ = FFIDynBox::<dyn Foo + Send>::new(std::rc::Rc::new(0));
+}
@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Node {
+	fn child(&self) -> &dyn Node;
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	fn a(&self);
+	#[ffi_trait(since = 2)]
+	fn b(self);
+}
+
+fn main() {}
@@ -0,0 +1,18 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	#[ffi_trait(since = 2)]
+	fn a(&self);
+	fn b(&self);
+}
+
+#[ffi_trait]
+trait Bar {
+	#[ffi_trait(since = 3)]
+	fn a(&self);
+	#[ffi_trait(since = 2)]
+	fn b(&self);
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Named {
+	fn name(&self) -> u32;
+	#[ffi_trait(since = 2)]
+	fn nickname(&self) -> u32;
+}
+
+#[ffi_trait]
+trait Animal: Named {
+	fn legs(&self) -> u32;
+}
+
+fn main() {}
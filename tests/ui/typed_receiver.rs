@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	fn a(self: std::rc::Rc<Self>);
+}
+
+fn main() {}
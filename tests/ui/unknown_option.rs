@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait(fast)]
+trait Foo {
+	fn a(&self);
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use ffi_trait::*;
+
+#[ffi_trait]
+trait Foo {
+	fn a<'x, 'y>(&self, x: &'x u8, y: &'y u8) where 'x: 'y;
+}
+
+fn main() {}
@@ -0,0 +1,76 @@
+#![cfg(feature = "std")]
+
+use ffi_trait::*;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+#[ffi_trait(on_panic = "abort")]
+pub trait Parser {
+	fn parse(&self, x: u32) -> u32;
+	#[ffi_trait(on_panic = "catch")]
+	fn check(&self, x: u32) -> u32;
+	#[ffi_trait(on_panic = "catch")]
+	fn reset(&mut self);
+	#[ffi_trait(on_panic = "unwind")]
+	extern "C-unwind" fn verify(&self, x: u32);
+}
+
+#[ffi_trait(on_panic = "catch")]
+pub trait Logger {
+	fn log(&self, level: u8);
+}
+
+struct Strict;
+
+impl Parser for Strict {
+	fn parse(&self, x: u32) -> u32 { x + 1 }
+	fn check(&self, x: u32) -> u32 {
+		assert!(x < 10, "too big");
+		x
+	}
+	fn reset(&mut self) { panic!("can't reset") }
+	fn verify(&self, x: u32) { assert!(x < 10, "too big") }
+}
+
+#[test]
+fn abort_passes_through() {
+	let mut s = Strict;
+	let r: FFIDynMut<dyn Parser> = s.dyn_mut();
+	assert_eq!(r.parse(1), 2);
+}
+
+#[test]
+fn c_unwind() {
+	let mut s = Strict;
+	let r: FFIDynMut<dyn Parser> = s.dyn_mut();
+	r.verify(1);
+	assert!(catch_unwind(AssertUnwindSafe(|| r.verify(10))).is_err());
+}
+
+#[test]
+fn catch() {
+	let mut s = Strict;
+	let vt = <dyn Parser as VtableFor<Strict>>::VTABLE;
+	let data = std::ptr::NonNull::from(&mut s).cast();
+	unsafe {
+		assert_eq!((vt.check)(data, 3).into_option(), Some(3));
+		assert!((vt.check)(data, 30).panicked);
+		assert!((vt.reset)(data).panicked);
+	}
+
+	// the wrappers panic again on the Rust side
+	let mut r: FFIDynMut<dyn Parser> = s.dyn_mut();
+	assert_eq!(r.check(3), 3);
+	assert!(catch_unwind(AssertUnwindSafe(|| r.reset())).is_err());
+}
+
+#[test]
+fn header() {
+	let h = <dyn Parser as CHeader>::C_HEADER;
+	assert!(h.contains("typedef struct Parser_check_result {\n\tbool panicked;\n\tuint32_t value;\n} Parser_check_result;"));
+	assert!(h.contains("typedef struct Parser_reset_result {\n\tbool panicked;\n} Parser_reset_result;"));
+	assert!(h.contains("\tParser_check_result (*check)(void *self, uint32_t arg0);"));
+	assert!(h.contains("\tuint32_t (*parse)(void *self, uint32_t arg0);"));
+	assert!(h.contains("\tvoid (*verify)(void *self, uint32_t arg0);"));
+	assert!(<dyn Logger as CHeader>::C_HEADER.contains("\tLogger_log_result (*log)(void *self, uint8_t arg0);"));
+}
@@ -0,0 +1,165 @@
+use ffi_trait::*;
+
+mod common;
+use common::CDir;
+
+use std::mem::offset_of;
+use std::ptr::NonNull;
+
+#[ffi_trait]
+pub trait Greeter {
+	fn id(&self) -> u32;
+	#[ffi_trait(since = 2)]
+	fn greet(&self, times: u32) -> u32;
+	#[ffi_trait(since = 3)]
+	fn wave(&self);
+}
+
+struct Native;
+
+impl Greeter for Native {
+	fn id(&self) -> u32 { 1 }
+	fn greet(&self, times: u32) -> u32 { times * 2 }
+	fn wave(&self) {}
+}
+
+type GreeterBuilder = <dyn Greeter as VtableBuilder>::Builder;
+
+unsafe extern "C" fn id(_: NonNull<()>) -> u32 { 7 }
+unsafe extern "C" fn greet(_: NonNull<()>, times: u32) -> u32 { times }
+
+#[test]
+fn native() {
+	let r: FFIDynRef<dyn Greeter> = Native.dyn_ref();
+	assert_eq!(r.try_greet(3), Some(6));
+	assert_eq!(r.try_wave(), Some(()));
+	assert_eq!(r.greet(4), 8);
+}
+
+#[test]
+fn older_plugin() {
+	// what a plugin built against the first version of the trait would hand over
+	let vt = GreeterBuilder {
+		__ffi_trait__align: 1,
+		__ffi_trait__vtable_size: offset_of!(GreeterBuilder, greet),
+		id: Some(id),
+		..Default::default()
+	};
+	let r = unsafe { FFIDynRef::<dyn Greeter>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	assert_eq!(r.id(), 7);
+	assert_eq!(r.try_greet(1), None);
+	assert_eq!(r.try_wave(), None);
+
+	// the second version, which left out nothing but may leave methods null
+	let vt = GreeterBuilder {
+		__ffi_trait__align: 1,
+		__ffi_trait__vtable_size: offset_of!(GreeterBuilder, wave),
+		id: Some(id),
+		greet: Some(greet),
+		..Default::default()
+	};
+	let r = unsafe { FFIDynRef::<dyn Greeter>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	assert_eq!(r.try_greet(5), Some(5));
+	assert_eq!(r.try_wave(), None);
+}
+
+// a plugin built against the first version, whose vtable ends before `greet`
+#[cfg(target_os = "linux")]
+const OLD_PLUGIN: &str = r#"
+#include <stddef.h>
+#include <stdlib.h>
+#include "Greeter.h"
+
+typedef struct Greeter_vtable_v1 {
+	size_t size;
+	size_t align;
+	void (*drop_in_place)(void *);
+	void (*dealloc)(void *);
+	size_t vtable_size;
+	const void *rust;
+	uint32_t (*id)(void *self);
+} Greeter_vtable_v1;
+
+_Static_assert(sizeof(Greeter_vtable_v1) == offsetof(Greeter_vtable, greet), "the first version ends before `greet`");
+
+static uint32_t id(void *self) {
+	return *(uint32_t *)self;
+}
+
+/* on the heap, so that reading past its end is caught by sanitizers */
+const Greeter_vtable_v1 *plugin_vtable(void) {
+	Greeter_vtable_v1 *vt = malloc(sizeof *vt);
+	vt->size = sizeof(uint32_t);
+	vt->align = _Alignof(uint32_t);
+	vt->drop_in_place = NULL;
+	vt->dealloc = NULL;
+	vt->vtable_size = sizeof *vt;
+	vt->rust = NULL;
+	vt->id = id;
+	return vt;
+}
+"#;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+	fn dlopen(filename: *const std::os::raw::c_char, flag: std::os::raw::c_int) -> *mut std::os::raw::c_void;
+	fn dlsym(handle: *mut std::os::raw::c_void, symbol: *const std::os::raw::c_char) -> *mut std::os::raw::c_void;
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn c_older_plugin() {
+	use std::ffi::CString;
+
+	let dir = CDir::new("versioning");
+	dir.write("Greeter.h", <dyn Greeter as CHeader>::C_HEADER);
+	dir.write("plugin.c", OLD_PLUGIN);
+	dir.cc(&["-shared", "-fPIC", "-o", "libplugin.so", "plugin.c"]);
+
+	unsafe {
+		let path = CString::new(dir.join("libplugin.so").to_str().unwrap()).unwrap();
+		let lib = dlopen(path.as_ptr(), 2 /* RTLD_NOW */);
+		assert!(!lib.is_null());
+		let name = CString::new("plugin_vtable").unwrap();
+		let sym = dlsym(lib, name.as_ptr());
+		assert!(!sym.is_null());
+		let plugin_vtable: unsafe extern "C" fn() -> NonNull<GreeterBuilder> = std::mem::transmute(sym);
+
+		let mut data = 9u32;
+		let r = FFIDynRef::<dyn Greeter>::from_foreign(NonNull::from(&mut data).cast(), plugin_vtable()).unwrap();
+		assert_eq!(r.id(), 9);
+		assert_eq!(r.try_greet(1), None);
+		assert_eq!(r.try_wave(), None);
+	}
+}
+
+#[test]
+#[should_panic(expected = "ffi_trait method `wave` is missing from the vtable")]
+fn missing_method() {
+	let vt = GreeterBuilder {
+		__ffi_trait__align: 1,
+		id: Some(id),
+		..Default::default()
+	};
+	let r = unsafe { FFIDynRef::<dyn Greeter>::from_foreign(NonNull::dangling(), NonNull::from(&vt)) }.unwrap();
+	r.wave();
+}
+
+#[test]
+fn too_short() {
+	let vt = GreeterBuilder {
+		__ffi_trait__align: 1,
+		__ffi_trait__vtable_size: offset_of!(GreeterBuilder, id),
+		id: Some(id),
+		..Default::default()
+	};
+	assert_eq!(<dyn Greeter>::validate(&vt), Err(VtableError::TooShort(offset_of!(GreeterBuilder, id))));
+}
+
+#[test]
+fn header() {
+	let h = <dyn Greeter as CHeader>::C_HEADER;
+	assert!(h.contains("\tsize_t vtable_size;"));
+	assert!(h.contains("\tuint32_t (*greet)(void *self, uint32_t arg0); /* since 2, may be missing */"));
+	assert!(h.contains("static inline bool Greeter_has_wave(Greeter_ptr self) {"));
+}